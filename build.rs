@@ -0,0 +1,49 @@
+extern crate cfgrammar;
+extern crate lrlex;
+extern crate lrpar;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use cfgrammar::{NTIdx, TIdx};
+use cfgrammar::yacc::{yacc_grm, YaccKind};
+use lrlex::LexerBuilder;
+use lrpar::CTParserBuilder;
+
+static LEX_PATH: &'static str = "grammar/lexer.l";
+static YACC_PATH: &'static str = "grammar/grammar.y";
+
+// Builds the lexer and parser for grammar/ into OUT_DIR, so that the compiler
+// doesn't need to find the grammar files at runtime.
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let lex_rule_ids = CTParserBuilder::new()
+        .process_file(YACC_PATH, &out_dir)
+        .expect("Invalid grammar");
+    LexerBuilder::new()
+        .rule_ids_map(lex_rule_ids)
+        .process_file(LEX_PATH, &out_dir)
+        .expect("Invalid lexer");
+    write_symbol_names(Path::new(&out_dir).join("symbol_names.rs").as_path());
+    println!("cargo:rerun-if-changed={}", LEX_PATH);
+    println!("cargo:rerun-if-changed={}", YACC_PATH);
+}
+
+// The generated parser doesn't carry the names of the grammar's symbols, which
+// the compiler dispatches on, so they're written out separately.
+fn write_symbol_names(out_path: &Path) {
+    let mut grms = String::new();
+    File::open(YACC_PATH).unwrap().read_to_string(&mut grms).unwrap();
+    let grm = yacc_grm(YaccKind::Original, &grms).expect("Invalid grammar");
+    let nonterms: Vec<String> = (0..grm.nonterms_len())
+        .map(|i| format!("{:?}", grm.nonterm_name(NTIdx::from(i))))
+        .collect();
+    let terms: Vec<String> = (0..grm.terms_len())
+        .map(|i| format!("{:?}", grm.term_name(TIdx::from(i))))
+        .collect();
+    let mut f = File::create(out_path).unwrap();
+    write!(f, "static NONTERM_NAMES: &'static [&'static str] = &[{}];\n", nonterms.join(", ")).unwrap();
+    write!(f, "static TERM_NAMES: &'static [Option<&'static str>] = &[{}];\n", terms.join(", ")).unwrap();
+}
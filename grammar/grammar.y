@@ -2,10 +2,95 @@
 %%
 
 prog : prog class_def
+     | prog enum_def
+     | prog trait_def
+     | prog const_decl "SEMI"
+     | prog import_stmt "SEMI"
      | class_def
+     | enum_def
+     | trait_def
+     | const_decl "SEMI"
+     | import_stmt "SEMI"
      ;
 
-class_def : "CLASS" "IDENTIFIER" "LPAREN" parent_class_opt "RPAREN" block;
+import_stmt : "IMPORT" "IDENTIFIER"
+            | "FROM" "IDENTIFIER" "IMPORT" import_list
+            ;
+
+import_list : "IDENTIFIER"
+            | import_list "COMMA" "IDENTIFIER"
+            ;
+
+class_def : "CLASS" "IDENTIFIER" "LPAREN" parent_class_opt "RPAREN" implements_opt class_body;
+
+implements_opt :
+               | "IMPLEMENTS" trait_list
+               ;
+
+trait_list : "IDENTIFIER"
+           | trait_list "COMMA" "IDENTIFIER"
+           ;
+
+class_body : "LBRACE" class_members_opt "RBRACE";
+
+class_members_opt :
+                  | class_members
+                  ;
+
+class_members : class_member
+              | class_members "SEMI" class_member
+              ;
+
+class_member : modifiers_opt func_def
+             | modifiers_opt field_decl
+             | modifiers_opt const_decl
+             ;
+
+modifiers_opt :
+              | modifiers
+              ;
+
+modifiers : modifier
+          | modifiers modifier
+          ;
+
+modifier : "STATIC"
+         | "PRIVATE"
+         | decorator
+         ;
+
+decorator : "AT" expression;
+
+field_decl : "FIELD" "IDENTIFIER" "EQ" expression
+           | "FIELD" "IDENTIFIER" "COLON" type_name "EQ" expression
+           ;
+
+const_decl : "CONST" "IDENTIFIER" "EQ" expression;
+
+trait_def : "TRAIT" "IDENTIFIER" "LBRACE" trait_members_opt "RBRACE";
+
+trait_members_opt :
+                  | trait_members
+                  ;
+
+trait_members : trait_member
+              | trait_members trait_member
+              ;
+
+trait_member : "DEF" "IDENTIFIER" "LPAREN" parameter_list_opt "RPAREN" return_type_opt "SEMI"
+             | func_def
+             | func_def "SEMI"
+             ;
+
+enum_def : "ENUM" "IDENTIFIER" "LBRACE" variant_list "RBRACE";
+
+variant_list : variant
+             | variant_list "COMMA" variant
+             ;
+
+variant : "IDENTIFIER"
+        | "IDENTIFIER" "LPAREN" parameter_list_opt "RPAREN"
+        ;
 
 parent_class_opt :
                 | "IDENTIFIER"
@@ -23,20 +108,89 @@ block_statements : statement
 
 statement : expression
           | if_statement
+          | assert_statement
           | let_statement
+          | const_decl
+          | compound_assignment
           | func_def
           | for_statement
+          | for_in_statement
+          | match_statement
           | try_except
           | raise
           ;
 
 if_statement : "IF" expression block;
 
-let_statement : "LET" "IDENTIFIER" "EQ" expression;
+assert_statement : "ASSERT" expression
+                 | "ASSERT" expression "COMMA" expression
+                 ;
+
+let_statement : "LET" "IDENTIFIER" "EQ" expression
+              | "LET" "IDENTIFIER" "COLON" type_name "EQ" expression
+              | "LET" "LPAREN" target_list "RPAREN" "EQ" expression
+              ;
+
+compound_assignment : "IDENTIFIER" "PLUSEQ" expression;
+
+target_list : "IDENTIFIER"
+            | target_list "COMMA" "IDENTIFIER"
+            ;
 
 for_statement : "FOR" "LPAREN" statement "SEMI" expression "SEMI" statement "RPAREN" block;
 
-func_def    : "DEF" "IDENTIFIER" "LPAREN" parameter_list_opt "RPAREN" block ;
+for_in_statement : "FOR" "IDENTIFIER" "IN" expression block
+                 | "FOR" "LPAREN" target_list "RPAREN" "IN" expression block
+                 ;
+
+match_statement : "MATCH" expression "LBRACE" match_arms "RBRACE";
+
+match_arms : match_arm
+           | match_arms "COMMA" match_arm
+           ;
+
+match_arm : pattern "FATARROW" block;
+
+pattern : "IDENTIFIER"
+        | literal
+        | variant_pattern
+        | tuple_pattern
+        ;
+
+variant_pattern : "IDENTIFIER" "DOT" "IDENTIFIER"
+                | "IDENTIFIER" "DOT" "IDENTIFIER" "LPAREN" pattern_list_opt "RPAREN"
+                ;
+
+tuple_pattern : "LPAREN" "RPAREN"
+              | "LPAREN" pattern "COMMA" "RPAREN"
+              | "LPAREN" pattern "COMMA" pattern_list "RPAREN"
+              ;
+
+pattern_list_opt :
+                 | pattern_list
+                 ;
+
+pattern_list : pattern
+             | pattern_list "COMMA" pattern
+             ;
+
+func_def    : "DEF" "IDENTIFIER" "LPAREN" parameter_list_opt "RPAREN" return_type_opt contracts_opt block ;
+
+return_type_opt :
+                | "ARROW" type_name
+                ;
+
+contracts_opt :
+              | contracts
+              ;
+
+contracts : contract
+          | contracts contract
+          ;
+
+contract : "REQUIRES" expression
+         | "ENSURES" expression
+         ;
 
 try_except : "TRY" block "EXCEPT" block;
 
@@ -46,10 +200,16 @@ parameter_list_opt :
                    | parameter_list
                    ;
 
-parameter_list : "IDENTIFIER"
-               | parameter_list "COMMA" "IDENTIFIER"
+parameter_list : parameter
+               | parameter_list "COMMA" parameter
                ;
 
+parameter : "IDENTIFIER"
+          | "IDENTIFIER" "COLON" type_name
+          ;
+
+type_name : "IDENTIFIER";
+
 expression : variable
            | binary_expression
            | method_invocation
@@ -57,6 +217,9 @@ expression : variable
            | field_access
            | field_set
            | class_instance_creation
+           | tuple_literal
+           | index_expression
+           | yield_expression
            | literal
            ;
 
@@ -75,7 +238,9 @@ bin_op : "PLUS"
 
 method_invocation : "IDENTIFIER" "DOT" "IDENTIFIER" "LPAREN" arg_list_opt "RPAREN";
 
-method_invocation_same_class : "IDENTIFIER" "LPAREN" arg_list_opt "RPAREN";
+method_invocation_same_class : "IDENTIFIER" "LPAREN" arg_list_opt "RPAREN"
+                             | "IMPLEMENTS" "LPAREN" arg_list_opt "RPAREN"
+                             ;
 
 arg_list_opt :
              | arg_list
@@ -91,6 +256,15 @@ field_set : "IDENTIFIER" "DOT" "IDENTIFIER" "EQ" expression;
 
 class_instance_creation : "NEW" "IDENTIFIER" "LPAREN" arg_list_opt "RPAREN";
 
+tuple_literal : "LPAREN" "RPAREN"
+              | "LPAREN" expression "COMMA" "RPAREN"
+              | "LPAREN" expression "COMMA" arg_list "RPAREN"
+              ;
+
+index_expression : expression "LSQUARE" expression "RSQUARE";
+
+yield_expression : "YIELD" expression;
+
 literal : "INT_LITERAL"
         | "BOOL_LITERAL"
         | "STR_LITERAL"
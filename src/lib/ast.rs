@@ -0,0 +1,793 @@
+// The abstract syntax tree of a module. It's built from the parse tree here,
+// in one place, so that the passes after parsing don't depend on how the
+// grammar happens to be laid out. Every node records the span of source it
+// was parsed from, as byte offsets.
+
+use cfgrammar::TIdx;
+use lrpar::parser::Node;
+
+use parse::{node_end, node_start, SymbolNames};
+
+pub type Span = (usize, usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub decls: Vec<Decl>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Decl {
+    Class(ClassDef),
+    Enum(EnumDef),
+    Trait(TraitDef),
+    Const(ConstDecl),
+    Import(Import),
+}
+
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub module: Ident,
+    // Empty when the module itself is imported.
+    pub names: Vec<Ident>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassDef {
+    pub name: Ident,
+    pub parent: Option<Ident>,
+    pub traits: Vec<Ident>,
+    pub members: Vec<Member>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub is_static: bool,
+    pub is_private: bool,
+    pub decorators: Vec<Expr>,
+    pub kind: MemberKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum MemberKind {
+    Method(FuncDef),
+    Field(FieldDecl),
+    Const(ConstDecl),
+}
+
+impl Member {
+    pub fn name(&self) -> &Ident {
+        match self.kind {
+            MemberKind::Method(ref f) => &f.name,
+            MemberKind::Field(ref f) => &f.name,
+            MemberKind::Const(ref c) => &c.name,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldDecl {
+    pub name: Ident,
+    pub type_name: Option<Ident>,
+    pub value: Expr,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConstDecl {
+    pub name: Ident,
+    pub value: Expr,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumDef {
+    pub name: Ident,
+    pub variants: Vec<Variant>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub name: Ident,
+    // The names of the variant's values.
+    pub params: Vec<Param>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct TraitDef {
+    pub name: Ident,
+    pub methods: Vec<TraitMethod>,
+    pub span: Span,
+}
+
+// A method required by a trait, which classes without one of their own are
+// given if it has a default body.
+#[derive(Debug, Clone)]
+pub struct TraitMethod {
+    pub name: Ident,
+    pub params: Vec<Param>,
+    pub return_type: Option<Ident>,
+    pub default: Option<FuncDef>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct FuncDef {
+    pub name: Ident,
+    pub params: Vec<Param>,
+    pub return_type: Option<Ident>,
+    pub requires: Vec<Expr>,
+    pub ensures: Vec<Expr>,
+    pub body: Block,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: Ident,
+    pub type_name: Option<Ident>,
+    pub span: Span,
+}
+
+pub type Block = Vec<Stmt>;
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
+    Expr(Expr),
+    If { cond: Expr, body: Block },
+    Assert { cond: Expr, msg: Option<Expr> },
+    Let { target: Target, type_name: Option<Ident>, value: Expr },
+    Const(ConstDecl),
+    // `name += value`
+    CompoundAssign { name: Ident, value: Expr },
+    FuncDef(FuncDef),
+    For { init: Box<Stmt>, cond: Expr, step: Box<Stmt>, body: Block },
+    ForIn { target: Target, iterable: Expr, body: Block },
+    Match { scrutinee: Expr, arms: Vec<MatchArm> },
+    TryExcept { body: Block, handler: Block },
+    Raise,
+}
+
+// What a `let` or `for` binds: a single local, or the elements of a tuple.
+#[derive(Debug, Clone)]
+pub enum Target {
+    Name(Ident),
+    Tuple(Vec<Ident>),
+}
+
+impl Target {
+    pub fn names(&self) -> Vec<&Ident> {
+        match *self {
+            Target::Name(ref name) => vec![name],
+            Target::Tuple(ref names) => names.iter().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Block,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum PatternKind {
+    Wildcard,
+    Binding(Ident),
+    Literal(Literal),
+    Variant { enum_name: Ident, variant: Ident, fields: Vec<Pattern> },
+    Tuple(Vec<Pattern>),
+}
+
+impl Pattern {
+    // The locals the pattern binds, in source order.
+    pub fn bindings(&self) -> Vec<&Ident> {
+        match self.kind {
+            PatternKind::Binding(ref name) => vec![name],
+            PatternKind::Variant { ref fields, .. } | PatternKind::Tuple(ref fields) => {
+                fields.iter().flat_map(|f| f.bindings()).collect()
+            }
+            PatternKind::Wildcard | PatternKind::Literal(_) => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Var(Ident),
+    Binary { lhs: Box<Expr>, op: BinOp, rhs: Box<Expr> },
+    // `receiver.method(args)`, where the receiver is a local, a class or an
+    // enum.
+    MethodCall { receiver: Ident, method: Ident, args: Vec<Expr> },
+    // `name(args)`, calling a function or a method of the current class.
+    Call { name: Ident, args: Vec<Expr> },
+    Field { obj: Ident, field: Ident },
+    FieldSet { obj: Ident, field: Ident, value: Box<Expr> },
+    New { class: Ident, args: Vec<Expr> },
+    Tuple(Vec<Expr>),
+    Index { obj: Box<Expr>, index: Box<Expr> },
+    Yield(Box<Expr>),
+    Literal(Literal),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Lteq,
+    Gteq,
+    Lt,
+    Gt,
+    Eqeq,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    // The digits as written, which may not fit in an Int.
+    Int(String),
+    Bool(bool),
+    // The string's contents, with escapes removed.
+    Str(String),
+}
+
+impl Expr {
+    // The expressions directly inside this one, in source order.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self.kind {
+            ExprKind::Binary { ref lhs, ref rhs, .. } => vec![&**lhs, &**rhs],
+            ExprKind::MethodCall { ref args, .. } | ExprKind::Call { ref args, .. }
+                | ExprKind::New { ref args, .. } | ExprKind::Tuple(ref args) => args.iter().collect(),
+            ExprKind::FieldSet { ref value, .. } | ExprKind::Yield(ref value) => vec![&**value],
+            ExprKind::Index { ref obj, ref index } => vec![&**obj, &**index],
+            ExprKind::Var(_) | ExprKind::Field { .. } | ExprKind::Literal(_) => Vec::new(),
+        }
+    }
+}
+
+pub fn build(pt: &Node<u16>, grm: &dyn SymbolNames, input: &str) -> Program {
+    Builder { grm: grm, input: input }.program(pt)
+}
+
+struct Builder<'a> {
+    grm: &'a dyn SymbolNames,
+    input: &'a str,
+}
+
+fn children(node: &Node<u16>) -> &[Node<u16>] {
+    match *node {
+        Node::Nonterm { ref nodes, .. } => nodes,
+        Node::Term { .. } => &[]
+    }
+}
+
+impl<'a> Builder<'a> {
+    fn name(&self, node: &Node<u16>) -> &'a str {
+        match *node {
+            Node::Nonterm { nonterm_idx, .. } => self.grm.nonterm_name(nonterm_idx),
+            Node::Term { lexeme } => {
+                self.grm.term_name(TIdx::from(usize::from(lexeme.tok_id()))).unwrap()
+            }
+        }
+    }
+
+    fn text(&self, node: &Node<u16>) -> &'a str {
+        let (start, end) = self.span(node);
+        &self.input[start..end]
+    }
+
+    fn span(&self, node: &Node<u16>) -> Span {
+        let start = node_start(node).unwrap_or(0);
+        (start, node_end(node).unwrap_or(start))
+    }
+
+    fn ident(&self, node: &Node<u16>) -> Ident {
+        Ident { name: self.text(node).to_string(), span: self.span(node) }
+    }
+
+    // Lists in the grammar are left recursive, and often optional. This
+    // flattens one, returning the nodes under `node` named one of `items`,
+    // in order.
+    fn items<'n>(&self, node: &'n Node<u16>, items: &[&str]) -> Vec<&'n Node<u16>> {
+        let mut found = Vec::new();
+        for child in children(node) {
+            match *child {
+                Node::Nonterm { .. } if items.contains(&self.name(child)) => found.push(child),
+                Node::Nonterm { .. } => found.extend(self.items(child, items)),
+                Node::Term { .. } => ()
+            }
+        }
+        found
+    }
+
+    // Returns the IDENTIFIER terminals under `node`, in order.
+    fn idents(&self, node: &Node<u16>) -> Vec<Ident> {
+        match *node {
+            Node::Nonterm { ref nodes, .. } => nodes.iter().flat_map(|n| self.idents(n)).collect(),
+            Node::Term { .. } if self.name(node) == "IDENTIFIER" => vec![self.ident(node)],
+            Node::Term { .. } => Vec::new()
+        }
+    }
+
+    // prog : prog class_def
+    //      | prog enum_def
+    //      | prog trait_def
+    //      | prog const_decl "SEMI"
+    //      | prog import_stmt "SEMI"
+    //      | ...
+    //      ;
+    fn program(&self, pt: &Node<u16>) -> Program {
+        let decls = ["class_def", "enum_def", "trait_def", "const_decl", "import_stmt"];
+        let decls = self.items(pt, &decls).into_iter()
+            .map(|decl| match self.name(decl) {
+                "class_def" => Decl::Class(self.class_def(decl)),
+                "enum_def" => Decl::Enum(self.enum_def(decl)),
+                "trait_def" => Decl::Trait(self.trait_def(decl)),
+                "const_decl" => Decl::Const(self.const_decl(decl)),
+                _ => Decl::Import(self.import(decl))
+            })
+            .collect();
+        Program { decls: decls }
+    }
+
+    // import_stmt : "IMPORT" "IDENTIFIER"
+    //             | "FROM" "IDENTIFIER" "IMPORT" import_list
+    //             ;
+    fn import(&self, node: &Node<u16>) -> Import {
+        let nodes = children(node);
+        Import {
+            module: self.ident(&nodes[1]),
+            names: if nodes.len() == 4 { self.idents(&nodes[3]) } else { Vec::new() },
+            span: self.span(node),
+        }
+    }
+
+    // class_def : "CLASS" "IDENTIFIER" "LPAREN" parent_class_opt "RPAREN" implements_opt class_body;
+    // parent_class_opt :
+    //                  | "IDENTIFIER"
+    //                  ;
+    fn class_def(&self, node: &Node<u16>) -> ClassDef {
+        let nodes = children(node);
+        ClassDef {
+            name: self.ident(&nodes[1]),
+            parent: children(&nodes[3]).first().map(|p| self.ident(p)),
+            traits: self.idents(&nodes[5]),
+            members: self.items(&nodes[6], &["class_member"]).into_iter()
+                .map(|m| self.member(m))
+                .collect(),
+            span: self.span(node),
+        }
+    }
+
+    // class_member : modifiers_opt func_def
+    //              | modifiers_opt field_decl
+    //              | modifiers_opt const_decl
+    //              ;
+    // modifier : "STATIC"
+    //          | "PRIVATE"
+    //          | decorator
+    //          ;
+    // decorator : "AT" expression;
+    fn member(&self, node: &Node<u16>) -> Member {
+        let nodes = children(node);
+        let mut member = Member {
+            is_static: false,
+            is_private: false,
+            decorators: Vec::new(),
+            kind: match self.name(&nodes[1]) {
+                "func_def" => MemberKind::Method(self.func_def(&nodes[1])),
+                "field_decl" => MemberKind::Field(self.field_decl(&nodes[1])),
+                _ => MemberKind::Const(self.const_decl(&nodes[1]))
+            },
+            span: self.span(node),
+        };
+        for modifier in self.items(&nodes[0], &["modifier"]) {
+            let modifier = &children(modifier)[0];
+            match self.name(modifier) {
+                "STATIC" => member.is_static = true,
+                "PRIVATE" => member.is_private = true,
+                _ => member.decorators.push(self.expr(&children(modifier)[1]))
+            }
+        }
+        member
+    }
+
+    // field_decl : "FIELD" "IDENTIFIER" "EQ" expression
+    //            | "FIELD" "IDENTIFIER" "COLON" type_name "EQ" expression
+    //            ;
+    fn field_decl(&self, node: &Node<u16>) -> FieldDecl {
+        let nodes = children(node);
+        FieldDecl {
+            name: self.ident(&nodes[1]),
+            type_name: if nodes.len() == 6 { Some(self.type_name(&nodes[3])) } else { None },
+            value: self.expr(nodes.last().unwrap()),
+            span: self.span(node),
+        }
+    }
+
+    // const_decl : "CONST" "IDENTIFIER" "EQ" expression;
+    fn const_decl(&self, node: &Node<u16>) -> ConstDecl {
+        let nodes = children(node);
+        ConstDecl {
+            name: self.ident(&nodes[1]),
+            value: self.expr(&nodes[3]),
+            span: self.span(node),
+        }
+    }
+
+    // enum_def : "ENUM" "IDENTIFIER" "LBRACE" variant_list "RBRACE";
+    // variant : "IDENTIFIER"
+    //         | "IDENTIFIER" "LPAREN" parameter_list_opt "RPAREN"
+    //         ;
+    fn enum_def(&self, node: &Node<u16>) -> EnumDef {
+        let nodes = children(node);
+        let variants = self.items(&nodes[3], &["variant"]).into_iter()
+            .map(|v| {
+                let parts = children(v);
+                Variant {
+                    name: self.ident(&parts[0]),
+                    params: if parts.len() > 1 { self.params(&parts[2]) } else { Vec::new() },
+                    span: self.span(v),
+                }
+            })
+            .collect();
+        EnumDef { name: self.ident(&nodes[1]), variants: variants, span: self.span(node) }
+    }
+
+    // trait_def : "TRAIT" "IDENTIFIER" "LBRACE" trait_members_opt "RBRACE";
+    // trait_member : "DEF" "IDENTIFIER" "LPAREN" parameter_list_opt "RPAREN" return_type_opt "SEMI"
+    //              | func_def
+    //              | func_def "SEMI"
+    //              ;
+    fn trait_def(&self, node: &Node<u16>) -> TraitDef {
+        let nodes = children(node);
+        let methods = self.items(&nodes[3], &["trait_member"]).into_iter()
+            .map(|m| {
+                let parts = children(m);
+                if self.name(&parts[0]) == "func_def" {
+                    let func = self.func_def(&parts[0]);
+                    TraitMethod {
+                        name: func.name.clone(),
+                        params: func.params.clone(),
+                        return_type: func.return_type.clone(),
+                        span: self.span(m),
+                        default: Some(func),
+                    }
+                }
+                else {
+                    TraitMethod {
+                        name: self.ident(&parts[1]),
+                        params: self.params(&parts[3]),
+                        return_type: self.return_type(&parts[5]),
+                        default: None,
+                        span: self.span(m),
+                    }
+                }
+            })
+            .collect();
+        TraitDef { name: self.ident(&nodes[1]), methods: methods, span: self.span(node) }
+    }
+
+    // func_def : "DEF" "IDENTIFIER" "LPAREN" parameter_list_opt "RPAREN" return_type_opt
+    //            contracts_opt block ;
+    // contract : "REQUIRES" expression
+    //          | "ENSURES" expression
+    //          ;
+    fn func_def(&self, node: &Node<u16>) -> FuncDef {
+        let nodes = children(node);
+        let mut requires = Vec::new();
+        let mut ensures = Vec::new();
+        for contract in self.items(&nodes[6], &["contract"]) {
+            let parts = children(contract);
+            match self.name(&parts[0]) {
+                "REQUIRES" => requires.push(self.expr(&parts[1])),
+                _ => ensures.push(self.expr(&parts[1]))
+            }
+        }
+        FuncDef {
+            name: self.ident(&nodes[1]),
+            params: self.params(&nodes[3]),
+            return_type: self.return_type(&nodes[5]),
+            requires: requires,
+            ensures: ensures,
+            body: self.block(&nodes[7]),
+            span: self.span(node),
+        }
+    }
+
+    // parameter : "IDENTIFIER"
+    //           | "IDENTIFIER" "COLON" type_name
+    //           ;
+    fn params(&self, node: &Node<u16>) -> Vec<Param> {
+        self.items(node, &["parameter"]).into_iter()
+            .map(|p| {
+                let parts = children(p);
+                Param {
+                    name: self.ident(&parts[0]),
+                    type_name: if parts.len() == 3 { Some(self.type_name(&parts[2])) } else { None },
+                    span: self.span(p),
+                }
+            })
+            .collect()
+    }
+
+    // return_type_opt :
+    //                 | "ARROW" type_name
+    //                 ;
+    fn return_type(&self, node: &Node<u16>) -> Option<Ident> {
+        children(node).get(1).map(|t| self.type_name(t))
+    }
+
+    // type_name : "IDENTIFIER";
+    fn type_name(&self, node: &Node<u16>) -> Ident {
+        self.ident(&children(node)[0])
+    }
+
+    // block : "LBRACE" block_statements_opt "RBRACE";
+    fn block(&self, node: &Node<u16>) -> Block {
+        self.items(node, &["statement"]).into_iter().map(|s| self.stmt(s)).collect()
+    }
+
+    // statement : expression
+    //           | if_statement
+    //           | assert_statement
+    //           | let_statement
+    //           | const_decl
+    //           | compound_assignment
+    //           | func_def
+    //           | for_statement
+    //           | for_in_statement
+    //           | match_statement
+    //           | try_except
+    //           | raise
+    //           ;
+    fn stmt(&self, node: &Node<u16>) -> Stmt {
+        let stmt = &children(node)[0];
+        let nodes = children(stmt);
+        let kind = match self.name(stmt) {
+            "expression" => StmtKind::Expr(self.expr(stmt)),
+            // if_statement : "IF" expression block;
+            "if_statement" => StmtKind::If { cond: self.expr(&nodes[1]), body: self.block(&nodes[2]) },
+            // assert_statement : "ASSERT" expression
+            //                  | "ASSERT" expression "COMMA" expression
+            //                  ;
+            "assert_statement" => StmtKind::Assert {
+                cond: self.expr(&nodes[1]),
+                msg: if nodes.len() == 4 { Some(self.expr(&nodes[3])) } else { None },
+            },
+            // let_statement : "LET" "IDENTIFIER" "EQ" expression
+            //               | "LET" "IDENTIFIER" "COLON" type_name "EQ" expression
+            //               | "LET" "LPAREN" target_list "RPAREN" "EQ" expression
+            //               ;
+            "let_statement" => match self.name(&nodes[1]) {
+                "IDENTIFIER" => StmtKind::Let {
+                    target: Target::Name(self.ident(&nodes[1])),
+                    type_name: if nodes.len() == 6 { Some(self.type_name(&nodes[3])) } else { None },
+                    value: self.expr(nodes.last().unwrap()),
+                },
+                _ => StmtKind::Let {
+                    target: Target::Tuple(self.idents(&nodes[2])),
+                    type_name: None,
+                    value: self.expr(&nodes[5]),
+                }
+            },
+            "const_decl" => StmtKind::Const(self.const_decl(stmt)),
+            // compound_assignment : "IDENTIFIER" "PLUSEQ" expression;
+            "compound_assignment" => StmtKind::CompoundAssign {
+                name: self.ident(&nodes[0]),
+                value: self.expr(&nodes[2]),
+            },
+            "func_def" => StmtKind::FuncDef(self.func_def(stmt)),
+            // for_statement : "FOR" "LPAREN" statement "SEMI" expression "SEMI" statement "RPAREN" block;
+            "for_statement" => StmtKind::For {
+                init: Box::new(self.stmt(&nodes[2])),
+                cond: self.expr(&nodes[4]),
+                step: Box::new(self.stmt(&nodes[6])),
+                body: self.block(&nodes[8]),
+            },
+            // for_in_statement : "FOR" "IDENTIFIER" "IN" expression block
+            //                  | "FOR" "LPAREN" target_list "RPAREN" "IN" expression block
+            //                  ;
+            "for_in_statement" if nodes.len() == 7 => StmtKind::ForIn {
+                target: Target::Tuple(self.idents(&nodes[2])),
+                iterable: self.expr(&nodes[5]),
+                body: self.block(&nodes[6]),
+            },
+            "for_in_statement" => StmtKind::ForIn {
+                target: Target::Name(self.ident(&nodes[1])),
+                iterable: self.expr(&nodes[3]),
+                body: self.block(&nodes[4]),
+            },
+            // match_statement : "MATCH" expression "LBRACE" match_arms "RBRACE";
+            // match_arm : pattern "FATARROW" block;
+            "match_statement" => StmtKind::Match {
+                scrutinee: self.expr(&nodes[1]),
+                arms: self.items(&nodes[3], &["match_arm"]).into_iter()
+                    .map(|arm| MatchArm {
+                        pattern: self.pattern(&children(arm)[0]),
+                        body: self.block(&children(arm)[2]),
+                        span: self.span(arm),
+                    })
+                    .collect(),
+            },
+            // try_except : "TRY" block "EXCEPT" block;
+            "try_except" => StmtKind::TryExcept {
+                body: self.block(&nodes[1]),
+                handler: self.block(&nodes[3]),
+            },
+            "raise" => StmtKind::Raise,
+            x => panic!("Unknown statement {}", x)
+        };
+        Stmt { kind: kind, span: self.span(node) }
+    }
+
+    // pattern : "IDENTIFIER"
+    //         | literal
+    //         | variant_pattern
+    //         | tuple_pattern
+    //         ;
+    // variant_pattern : "IDENTIFIER" "DOT" "IDENTIFIER"
+    //                 | "IDENTIFIER" "DOT" "IDENTIFIER" "LPAREN" pattern_list_opt "RPAREN"
+    //                 ;
+    fn pattern(&self, node: &Node<u16>) -> Pattern {
+        let pattern = &children(node)[0];
+        let nodes = children(pattern);
+        let kind = match self.name(pattern) {
+            "IDENTIFIER" if self.text(pattern) == "_" => PatternKind::Wildcard,
+            "IDENTIFIER" => PatternKind::Binding(self.ident(pattern)),
+            "literal" => PatternKind::Literal(self.literal(pattern)),
+            "variant_pattern" => PatternKind::Variant {
+                enum_name: self.ident(&nodes[0]),
+                variant: self.ident(&nodes[2]),
+                fields: if nodes.len() > 3 { self.patterns(&nodes[4]) } else { Vec::new() },
+            },
+            _ => PatternKind::Tuple(self.patterns(pattern))
+        };
+        Pattern { kind: kind, span: self.span(node) }
+    }
+
+    fn patterns(&self, node: &Node<u16>) -> Vec<Pattern> {
+        self.items(node, &["pattern"]).into_iter().map(|p| self.pattern(p)).collect()
+    }
+
+    // expression : variable
+    //            | binary_expression
+    //            | method_invocation
+    //            | method_invocation_same_class
+    //            | field_access
+    //            | field_set
+    //            | class_instance_creation
+    //            | tuple_literal
+    //            | index_expression
+    //            | yield_expression
+    //            | literal
+    //            ;
+    fn expr(&self, node: &Node<u16>) -> Expr {
+        let exp = &children(node)[0];
+        let nodes = children(exp);
+        let kind = match self.name(exp) {
+            "variable" => ExprKind::Var(self.ident(&nodes[0])),
+            "binary_expression" => ExprKind::Binary {
+                lhs: Box::new(self.expr(&nodes[0])),
+                op: self.bin_op(&nodes[1]),
+                rhs: Box::new(self.expr(&nodes[2])),
+            },
+            // method_invocation : "IDENTIFIER" "DOT" "IDENTIFIER" "LPAREN" arg_list_opt "RPAREN";
+            "method_invocation" => ExprKind::MethodCall {
+                receiver: self.ident(&nodes[0]),
+                method: self.ident(&nodes[2]),
+                args: self.exprs(&nodes[4]),
+            },
+            // method_invocation_same_class : "IDENTIFIER" "LPAREN" arg_list_opt "RPAREN"
+            //                              | "IMPLEMENTS" "LPAREN" arg_list_opt "RPAREN"
+            //                              ;
+            "method_invocation_same_class" => ExprKind::Call {
+                name: self.ident(&nodes[0]),
+                args: self.exprs(&nodes[2]),
+            },
+            // field_access : "IDENTIFIER" "DOT" "IDENTIFIER";
+            "field_access" => ExprKind::Field {
+                obj: self.ident(&nodes[0]),
+                field: self.ident(&nodes[2]),
+            },
+            // field_set : "IDENTIFIER" "DOT" "IDENTIFIER" "EQ" expression;
+            "field_set" => ExprKind::FieldSet {
+                obj: self.ident(&nodes[0]),
+                field: self.ident(&nodes[2]),
+                value: Box::new(self.expr(&nodes[4])),
+            },
+            // class_instance_creation : "NEW" "IDENTIFIER" "LPAREN" arg_list_opt "RPAREN";
+            "class_instance_creation" => ExprKind::New {
+                class: self.ident(&nodes[1]),
+                args: self.exprs(&nodes[3]),
+            },
+            // tuple_literal : "LPAREN" "RPAREN"
+            //               | "LPAREN" expression "COMMA" "RPAREN"
+            //               | "LPAREN" expression "COMMA" arg_list "RPAREN"
+            //               ;
+            "tuple_literal" => ExprKind::Tuple(self.exprs(exp)),
+            // index_expression : expression "LSQUARE" expression "RSQUARE";
+            "index_expression" => ExprKind::Index {
+                obj: Box::new(self.expr(&nodes[0])),
+                index: Box::new(self.expr(&nodes[2])),
+            },
+            // yield_expression : "YIELD" expression;
+            "yield_expression" => ExprKind::Yield(Box::new(self.expr(&nodes[1]))),
+            "literal" => ExprKind::Literal(self.literal(exp)),
+            x => panic!("Unknown expression {}", x)
+        };
+        Expr { kind: kind, span: self.span(node) }
+    }
+
+    // arg_list : expression
+    //          | arg_list "COMMA" expression
+    //          ;
+    fn exprs(&self, node: &Node<u16>) -> Vec<Expr> {
+        self.items(node, &["expression"]).into_iter().map(|e| self.expr(e)).collect()
+    }
+
+    // bin_op : "PLUS"
+    //        | "MINUS"
+    //        | "LTEQ"
+    //        | "GTEQ"
+    //        | "LT"
+    //        | "GT"
+    //        | "EQEQ"
+    //        ;
+    fn bin_op(&self, node: &Node<u16>) -> BinOp {
+        match self.name(&children(node)[0]) {
+            "PLUS"  => BinOp::Add,
+            "MINUS" => BinOp::Sub,
+            "LTEQ"  => BinOp::Lteq,
+            "GTEQ"  => BinOp::Gteq,
+            "LT"    => BinOp::Lt,
+            "GT"    => BinOp::Gt,
+            "EQEQ"  => BinOp::Eqeq,
+            x => panic!("Unknown operator {}", x)
+        }
+    }
+
+    // literal : "INT_LITERAL"
+    //         | "BOOL_LITERAL"
+    //         | "STR_LITERAL"
+    //         ;
+    fn literal(&self, node: &Node<u16>) -> Literal {
+        let lit = &children(node)[0];
+        let value = self.text(lit);
+        match self.name(lit) {
+            "INT_LITERAL" => Literal::Int(value.to_string()),
+            "BOOL_LITERAL" => Literal::Bool(value == "true"),
+            // A literal inserted by error recovery has no text.
+            _ if value.len() < 2 => Literal::Str(String::new()),
+            _ => Literal::Str(value[1..value.len() - 1].replace("\\\"", "\""))
+        }
+    }
+}
@@ -1,66 +1,278 @@
 use parse::Bytecode;
 use parse::Instr;
+use parse::{GLOBAL_INIT, STATIC_INIT, qualified_name};
 use std::collections::HashMap;
 
 static GLOBAL_NSPACE: &'static str = "global";
 static MAIN_FN: &'static str = "main";
+static ITER_FN: &'static str = "iter";
+static NEXT_FN: &'static str = "next";
+static STR_FN: &'static str = "__str__";
+static CALL_FN: &'static str = "__call__";
 const EXCEPTION_PTR: usize = 0;
 
+// Functions implemented by the VM itself. Calls to these names are compiled to
+// `Instr::CallBuiltin` rather than being looked up in the current class.
+static BUILTINS: &'static [&'static str] = &[
+    "range", "implements", "type", "isinstance", "getattr", "setattr", "hasattr", "fields", "methods"
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name)
+}
+
 #[derive(Debug, Clone)]
 pub enum NativeType {
     Int(i32),
     Double(f32),
     Bool(bool),
     Str(String),
+    Tuple(Vec<NativeType>),
+    Range(i32, i32, i32),
+    Variant(String, String, Vec<NativeType>),
+    Iterator(usize),
+    Generator(usize),
     ObjectRef(usize),
+    Trait(String),
+    Class(String),
+    Function(String, String),
     NoneType,
+    // What IterNext pushes once an iterator has run out, so that None can
+    // still be one of its values. User code never sees it.
+    Exhausted,
 }
 
 impl NativeType {
     fn pretty(&self) -> String {
+        self.pretty_with(&mut |_| None)
+    }
+
+    // Like `pretty`, but objects are shown as `obj_str` describes them, if it
+    // returns a description.
+    fn pretty_with<F>(&self, obj_str: &mut F) -> String
+        where F: FnMut(usize) -> Option<String>
+    {
         match *self {
             NativeType::Int(ref x) => x.to_string(),
             NativeType::Double(ref x) => x.to_string(),
             NativeType::Bool(ref x) => x.to_string(),
             NativeType::Str(ref x) => x.to_string(),
-            NativeType::ObjectRef(ref x) => format!("&{}",x.to_string()),
-            NativeType::NoneType => "None".to_string()
+            NativeType::Tuple(ref x) => {
+                let elems: Vec<String> = x.iter().map(|e| e.pretty_with(obj_str)).collect();
+                if elems.len() == 1 {
+                    format!("({},)", elems[0])
+                }
+                else {
+                    format!("({})", elems.join(", "))
+                }
+            }
+            NativeType::Range(start, stop, step) => format!("range({}, {}, {})", start, stop, step),
+            NativeType::Variant(ref enum_name, ref variant, ref payload) => {
+                if payload.is_empty() {
+                    format!("{}.{}", enum_name, variant)
+                }
+                else {
+                    let elems: Vec<String> = payload.iter().map(|e| e.pretty_with(obj_str)).collect();
+                    format!("{}.{}({})", enum_name, variant, elems.join(", "))
+                }
+            }
+            NativeType::Iterator(_) => "<iterator>".to_string(),
+            NativeType::Generator(_) => "<generator>".to_string(),
+            NativeType::ObjectRef(ref x) => match obj_str(*x) {
+                Some(x) => x,
+                None => format!("&{}",x.to_string()),
+            },
+            NativeType::Trait(ref x) => format!("<trait {}>", x),
+            NativeType::Class(ref x) => format!("<class {}>", x),
+            NativeType::Function(ref cls, ref name) => format!("<function {}.{}>", cls, name),
+            NativeType::NoneType => "None".to_string(),
+            NativeType::Exhausted => "<exhausted>".to_string(),
+        }
+    }
+
+    // Numbers compare by value across Int and Double. Everything else is
+    // compared structurally, except for references, which compare by
+    // identity. Values of unrelated types can't be compared, so None is
+    // returned for them.
+    fn equals(&self, other: &NativeType) -> Option<bool> {
+        match (self, other) {
+            (&NativeType::Int(x), &NativeType::Int(y))         => Some(x == y),
+            (&NativeType::Int(x), &NativeType::Double(y))      => Some(x as f32 == y),
+            (&NativeType::Double(x), &NativeType::Int(y))      => Some(x == y as f32),
+            (&NativeType::Double(x), &NativeType::Double(y))   => Some(x == y),
+            (&NativeType::Bool(x), &NativeType::Bool(y))       => Some(x == y),
+            (&NativeType::Str(ref x), &NativeType::Str(ref y)) => Some(x == y),
+            (&NativeType::Tuple(ref x), &NativeType::Tuple(ref y)) => all_equal(x, y),
+            (&NativeType::Range(a, b, c), &NativeType::Range(x, y, z)) => Some((a, b, c) == (x, y, z)),
+            (&NativeType::Variant(ref e1, ref v1, ref p1), &NativeType::Variant(ref e2, ref v2, ref p2)) => {
+                if e1 != e2 || v1 != v2 { Some(false) } else { all_equal(p1, p2) }
+            }
+            (&NativeType::Iterator(x), &NativeType::Iterator(y))   => Some(x == y),
+            (&NativeType::Generator(x), &NativeType::Generator(y)) => Some(x == y),
+            (&NativeType::ObjectRef(x), &NativeType::ObjectRef(y)) => Some(x == y),
+            (&NativeType::Trait(ref x), &NativeType::Trait(ref y)) => Some(x == y),
+            (&NativeType::Class(ref x), &NativeType::Class(ref y)) => Some(x == y),
+            (&NativeType::Function(ref c1, ref f1), &NativeType::Function(ref c2, ref f2)) => {
+                Some(c1 == c2 && f1 == f2)
+            }
+            (&NativeType::NoneType, &NativeType::NoneType)         => Some(true),
+            _ => None
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match *self {
+            NativeType::Int(_) => "Int",
+            NativeType::Double(_) => "Double",
+            NativeType::Bool(_) => "Bool",
+            NativeType::Str(_) => "Str",
+            NativeType::Tuple(_) => "Tuple",
+            NativeType::Range(..) => "Range",
+            NativeType::Variant(..) => "Variant",
+            NativeType::Iterator(_) => "Iterator",
+            NativeType::Generator(_) => "Generator",
+            NativeType::ObjectRef(_) => "Object",
+            NativeType::Trait(_) => "Trait",
+            NativeType::Class(_) => "Class",
+            NativeType::Function(..) => "Function",
+            NativeType::NoneType => "NoneType",
+            NativeType::Exhausted => "Exhausted",
         }
     }
 }
 
 #[derive(Clone)]
 struct Object {
+    class: String,
     fields: HashMap<String, NativeType>,
 }
 
 impl Object {
-    fn new() -> Object {
+    fn new(class: String) -> Object {
         Object {
+            class: class,
             fields: HashMap::new()
         }
     }
 }
 
+// The state of an iterator over one of the built-in iterable types. Iterators
+// over user objects are driven by the object's own `next` method instead.
+enum Iter {
+    Seq(Vec<NativeType>, usize),
+    Range(i32, i32, i32),
+}
+
+impl Iter {
+    fn next(&mut self) -> NativeType {
+        match *self {
+            Iter::Seq(ref elems, ref mut pos) => {
+                match elems.get(*pos) {
+                    Some(x) => {
+                        *pos += 1;
+                        x.clone()
+                    }
+                    None => NativeType::Exhausted
+                }
+            }
+            Iter::Range(ref mut next, stop, step) => {
+                if (step > 0 && *next < stop) || (step < 0 && *next > stop) {
+                    let value = *next;
+                    *next += step;
+                    NativeType::Int(value)
+                }
+                else {
+                    NativeType::Exhausted
+                }
+            }
+        }
+    }
+}
+
+// A suspended call to a generator function. Its frame is detached from
+// `VM::frames` between resumes and reattached on top of the resumer's frame.
+struct Generator {
+    frame: Option<Frame>,
+    pc: usize,
+    started: bool,
+    finished: bool,
+}
+
 pub struct VM {
     heap: Vec<Object>,
+    iterators: Vec<Iter>,
+    generators: Vec<Generator>,
+    statics: HashMap<(String, String), NativeType>,
+    globals: HashMap<String, NativeType>,
+    // Methods replaced by decorators, which are called instead of the
+    // method's own code.
+    bindings: HashMap<(String, String), NativeType>,
     bytecode: Bytecode,
     frames: Vec<Frame>,
     pc: usize,
+    // The message of the exception which stopped the program, if one did.
+    exception: Option<String>,
 }
 
 impl VM {
     pub fn new(bytecode: Bytecode) -> VM {
         VM {
             heap: Vec::new(),
+            iterators: Vec::new(),
+            generators: Vec::new(),
+            statics: HashMap::new(),
+            globals: HashMap::new(),
+            bindings: HashMap::new(),
             bytecode: bytecode,
             frames: Vec::new(),
             pc: 0,
+            exception: None,
         }
     }
 
     pub fn run(&mut self) -> Option<NativeType> {
+        // Imported modules' constants are initialised before those of the
+        // modules importing them.
+        let mut globals = self.bytecode.imported_globals.clone();
+        globals.push(GLOBAL_NSPACE.to_string());
+        for class_name in globals {
+            let global_init = (class_name, GLOBAL_INIT.to_string());
+            if self.bytecode.labels.contains_key(&global_init) {
+                if self.call_sync(global_init, Vec::new()).is_none() {
+                    return None
+                }
+            }
+        }
+        // Static fields are initialised before main, in declaration order.
+        for class_name in self.bytecode.static_init_order.clone() {
+            let key = (class_name, STATIC_INIT.to_string());
+            if self.call_sync(key, Vec::new()).is_none() {
+                return None
+            }
+        }
         self.enter_main();
+        self.execute(0)
+    }
+
+    // Calls `key` and runs it to completion before returning its result. None
+    // is returned if the call raised an exception.
+    fn call_sync(&mut self, key: (String, String), args: Vec<NativeType>) -> Option<NativeType> {
+        let saved_pc = self.pc;
+        self.frames.push(Frame::new(key.1.to_string(), Vec::new(), saved_pc));
+        let depth = self.frames.len();
+        self.enter_fn(key, args);
+        self.execute(depth);
+        if self.frames.len() < depth {
+            return None
+        }
+        let result = self.frames.pop().unwrap().pop();
+        self.pc = saved_pc;
+        Some(result)
+    }
+
+    // Executes instructions until the program ends, or until a return leaves
+    // only `stop_depth` frames on the call stack.
+    fn execute(&mut self, stop_depth: usize) -> Option<NativeType> {
         let mut result = None;
         loop {
             let bytecode_size = self.bytecode.bytecode.len();
@@ -83,11 +295,81 @@ impl VM {
                     frame.push(NativeType::Int(x.clone()));
                     self.pc += 1
                 }
+                Instr::PushTrait(ref x) => {
+                    let frame = self.frames.last_mut().unwrap();
+                    frame.push(NativeType::Trait(x.to_string()));
+                    self.pc += 1
+                },
+                Instr::PushClass(ref x) => {
+                    let frame = self.frames.last_mut().unwrap();
+                    frame.push(NativeType::Class(x.to_string()));
+                    self.pc += 1
+                },
+                Instr::PushNone => {
+                    let frame = self.frames.last_mut().unwrap();
+                    frame.push(NativeType::NoneType);
+                    self.pc += 1
+                },
+                Instr::PushFunction(ref class_name, ref fn_name) => {
+                    // Decorated methods evaluate to what they were replaced
+                    // with.
+                    let key = (class_name.to_string(), fn_name.to_string());
+                    let value = match self.bindings.get(&key) {
+                        Some(x) => x.clone(),
+                        None => NativeType::Function(key.0, key.1),
+                    };
+                    self.frames.last_mut().unwrap().push(value);
+                    self.pc += 1
+                },
                 Instr::PushStr(ref x) => {
                     let frame = self.frames.last_mut().unwrap();
                     frame.push(NativeType::Str(x.clone()));
                     self.pc += 1
                 }
+                Instr::BuildTuple(len) => {
+                    let frame = self.frames.last_mut().unwrap();
+                    frame.build_tuple(len);
+                    self.pc += 1
+                }
+                Instr::Index => {
+                    let frame = self.frames.last_mut().unwrap();
+                    frame.index();
+                    self.pc += 1
+                }
+                Instr::Unpack(len) => {
+                    let frame = self.frames.last_mut().unwrap();
+                    frame.unpack(len);
+                    self.pc += 1
+                }
+                Instr::BuildVariant(ref enum_name, ref variant, len) => {
+                    let frame = self.frames.last_mut().unwrap();
+                    let payload = frame.pop_n(len);
+                    frame.push(NativeType::Variant(enum_name.to_string(), variant.to_string(), payload));
+                    self.pc += 1
+                }
+                Instr::MatchVariant(ref enum_name, ref variant, len) => {
+                    let frame = self.frames.last_mut().unwrap();
+                    match frame.pop() {
+                        NativeType::Variant(ref e, ref v, ref payload)
+                            if e == enum_name && v == variant && payload.len() == len => {
+                            frame.stack.extend(payload.iter().cloned());
+                            frame.push(NativeType::Bool(true))
+                        }
+                        _ => frame.push(NativeType::Bool(false))
+                    }
+                    self.pc += 1
+                }
+                Instr::MatchTuple(len) => {
+                    let frame = self.frames.last_mut().unwrap();
+                    match frame.pop() {
+                        NativeType::Tuple(ref elems) if elems.len() == len => {
+                            frame.stack.extend(elems.iter().cloned());
+                            frame.push(NativeType::Bool(true))
+                        }
+                        _ => frame.push(NativeType::Bool(false))
+                    }
+                    self.pc += 1
+                }
                 Instr::Pop => {
                     let frame = self.frames.last_mut().unwrap();
                     frame.pop();
@@ -99,39 +381,58 @@ impl VM {
                     self.pc += 1
                 }
                 Instr::Add => {
-                    let frame = self.frames.last_mut().unwrap();
-                    frame.add();
-                    self.pc +=1
+                    if !self.call_operator("__add__") {
+                        let frame = self.frames.last_mut().unwrap();
+                        frame.add();
+                        self.pc +=1
+                    }
                 }
                 Instr::Sub => {
-                    let frame = self.frames.last_mut().unwrap();
-                    frame.sub();
-                    self.pc +=1
+                    if !self.call_operator("__sub__") {
+                        let frame = self.frames.last_mut().unwrap();
+                        frame.sub();
+                        self.pc +=1
+                    }
                 }
                 Instr::Lteq => {
-                    let frame = self.frames.last_mut().unwrap();
-                    frame.lteq();
-                    self.pc +=1
+                    if !self.call_operator("__le__") {
+                        let frame = self.frames.last_mut().unwrap();
+                        frame.lteq();
+                        self.pc +=1
+                    }
                 }
-                Instr::Gteq =>{
-                    let frame = self.frames.last_mut().unwrap();
-                    frame.gteq();
-                    self.pc +=1
+                Instr::Gteq => {
+                    if !self.call_operator("__ge__") {
+                        let frame = self.frames.last_mut().unwrap();
+                        frame.gteq();
+                        self.pc +=1
+                    }
                 }
-                Instr::Lt =>{
-                    let frame = self.frames.last_mut().unwrap();
-                    frame.lt();
-                    self.pc +=1
+                Instr::Lt => {
+                    if !self.call_operator("__lt__") {
+                        let frame = self.frames.last_mut().unwrap();
+                        frame.lt();
+                        self.pc +=1
+                    }
                 }
-                Instr::Gt =>{
-                    let frame = self.frames.last_mut().unwrap();
-                    frame.gt();
-                    self.pc +=1
+                Instr::Gt => {
+                    if !self.call_operator("__gt__") {
+                        let frame = self.frames.last_mut().unwrap();
+                        frame.gt();
+                        self.pc +=1
+                    }
+                }
+                Instr::Eqeq => {
+                    if !self.call_operator("__eq__") {
+                        let frame = self.frames.last_mut().unwrap();
+                        frame.eq();
+                        self.pc +=1
+                    }
                 }
-                Instr::Eqeq =>{
+                Instr::MatchEq => {
                     let frame = self.frames.last_mut().unwrap();
-                    frame.eq();
-                    self.pc +=1
+                    frame.match_eq();
+                    self.pc += 1
                 }
                 Instr::LoadVar(index) => {
                     let frame = self.frames.last_mut().unwrap();
@@ -147,10 +448,44 @@ impl VM {
                     let frame = self.frames.last_mut().unwrap();
                     frame.raise("Exception");
                 }
-                Instr::LoadGlobal(ref _name) => panic!("NotYetImplemented"),
-                Instr::StoreGlobal(ref _name) => panic!("NotYetImplemented"),
-                Instr::NewObject => {
-                    let obj = Object::new();
+                Instr::RaiseAssertion(line, col) => {
+                    let frame = self.frames.last_mut().unwrap();
+                    let msg = frame.pop().pretty();
+                    frame.raise(&format!("AssertionError: {} at line {}, column {}", msg, line, col));
+                },
+                Instr::RaiseError(ref msg) => {
+                    let frame = self.frames.last_mut().unwrap();
+                    frame.raise(msg);
+                }
+                Instr::LoadStatic(ref class_name, ref field_name) => {
+                    let key = (class_name.to_string(), field_name.to_string());
+                    let value = match self.statics.get(&key) {
+                        Some(x) => x.clone(),
+                        None => NativeType::NoneType,
+                    };
+                    self.frames.last_mut().unwrap().push(value);
+                    self.pc += 1
+                },
+                Instr::StoreStatic(ref class_name, ref field_name) => {
+                    let value = self.frames.last_mut().unwrap().pop();
+                    self.statics.insert((class_name.to_string(), field_name.to_string()), value);
+                    self.pc += 1
+                },
+                Instr::LoadGlobal(ref name) => {
+                    let value = match self.globals.get(name) {
+                        Some(x) => x.clone(),
+                        None => NativeType::NoneType,
+                    };
+                    self.frames.last_mut().unwrap().push(value);
+                    self.pc += 1
+                },
+                Instr::StoreGlobal(ref name) => {
+                    let value = self.frames.last_mut().unwrap().pop();
+                    self.globals.insert(name.to_string(), value);
+                    self.pc += 1
+                },
+                Instr::NewObject(ref class_name) => {
+                    let obj = Object::new(class_name.to_string());
                     self.heap.push(obj);
                     let obj_ref = self.heap.len() - 1;
                     let frame = self.frames.last_mut().unwrap();
@@ -158,29 +493,52 @@ impl VM {
                     self.pc += 1
                 },
                 Instr::LoadField(ref field_name) => {
-                    let frame = self.frames.last_mut().unwrap();
-                    let obj_ref = frame.pop();
-                    let obj = match obj_ref {
-                        NativeType::ObjectRef(x) => self.heap.get(x).unwrap(),
-                        _ => panic!("Not a valid object")
+                    let field_name = field_name.to_string();
+                    let obj_ref = self.frames.last_mut().unwrap().pop();
+                    let field = match obj_ref {
+                        NativeType::ObjectRef(x) => {
+                            if !self.check_field_access(x, &field_name) {
+                                None
+                            }
+                            else if let Some(field) = self.heap[x].fields.get(&field_name).cloned() {
+                                Some(field)
+                            }
+                            else {
+                                let msg = format!("AttributeError: {} has no field {}",
+                                                  self.heap[x].class, field_name);
+                                self.frames.last_mut().unwrap().raise(&msg);
+                                None
+                            }
+                        }
+                        x => {
+                            let msg = format!("TypeError: {} has no fields", x.type_name());
+                            self.frames.last_mut().unwrap().raise(&msg);
+                            None
+                        }
                     };
-                    let field = obj.fields.get(field_name)
-                        .expect("Field not found");
-                    frame.push(field.clone());
-                    self.pc += 1
+                    if let Some(field) = field {
+                        self.frames.last_mut().unwrap().push(field);
+                        self.pc += 1
+                    }
                 }
                 Instr::StoreField(ref field_name) => {
-                    let frame = self.frames.last_mut().unwrap();
-                    let obj_ref = frame.pop();
-                    let value = frame.pop();
+                    let field_name = field_name.to_string();
+                    let (obj_ref, value) = {
+                        let frame = self.frames.last_mut().unwrap();
+                        (frame.pop(), frame.pop())
+                    };
                     match obj_ref {
                         NativeType::ObjectRef(x) => {
-                            let obj = self.heap.get_mut(x).unwrap();
-                            obj.fields.insert(field_name.to_string(), value);
+                            if self.check_field_access(x, &field_name) {
+                                self.heap[x].fields.insert(field_name, value);
+                                self.pc += 1
+                            }
                         }
-                        _ => panic!("Not a valid object")
-                    };
-                    self.pc += 1
+                        x => {
+                            let msg = format!("TypeError: {} has no fields", x.type_name());
+                            self.frames.last_mut().unwrap().raise(&msg);
+                        }
+                    }
                 },
                 Instr::JumpIfTrue(pos) => {
                     let frame = self.frames.last_mut().unwrap();
@@ -200,40 +558,149 @@ impl VM {
                         self.pc += 1
                     }
                 },
+                Instr::JumpIfExhausted(pos) => {
+                    let frame = self.frames.last_mut().unwrap();
+                    if let Some(&NativeType::Exhausted) = frame.peek() {
+                        frame.pop();
+                        self.pc = pos
+                    }
+                    else {
+                        self.pc += 1
+                    }
+                },
                 Instr::Jump(pos) => self.pc = pos,
-                Instr::Call(ref class_name, ref fn_name) => {
-                    let ref key = (class_name.to_string(), fn_name.to_string());
-                    let fn_metadata = self.bytecode.symbols.get(&key.clone())
-                        .expect("Function not found");
-                    let mut locals = {
-                        let frame = self.frames.last_mut().unwrap();
-                        let mut locals = Vec::new();
-                        for _ in 0..fn_metadata.params_len() {
-                            locals.push(frame.pop())
-                        }
-                        locals
+                Instr::Call(ref class_name, ref fn_name) | Instr::CallUnchecked(ref class_name, ref fn_name) => {
+                    let checked = match self.bytecode.bytecode[self.pc] {
+                        Instr::CallUnchecked(..) => false,
+                        _ => true
                     };
-                    locals.reverse(); // TODO: This can be more efficient if we rework
-                                    // this to add args in reverse order in place
-                    let new_frame = Frame::new(fn_name.to_string(), locals, self.pc + 1);
-                    self.frames.push(new_frame);
-                    self.pc = self.bytecode.labels.get(key).unwrap().clone();
+                    match self.bytecode.lookup_method(class_name, fn_name) {
+                        None => {
+                            let msg = format!("AttributeError: {} has no method {}", class_name, fn_name);
+                            self.frames.last_mut().unwrap().raise(&msg);
+                        }
+                        Some(ref key) if self.check_access(&key.0, &key.1) => {
+                            let fn_metadata = self.bytecode.symbols.get(&key.clone()).unwrap();
+                            let mut locals = {
+                                let frame = self.frames.last_mut().unwrap();
+                                let mut locals = Vec::new();
+                                for _ in 0..fn_metadata.params_len() {
+                                    locals.push(frame.pop())
+                                }
+                                locals
+                            };
+                            locals.reverse(); // TODO: This can be more efficient if we rework
+                                            // this to add args in reverse order in place
+                            match self.bindings.get(key).cloned() {
+                                Some(callee) => self.call_value(callee, locals),
+                                None if checked => self.enter_fn(key.clone(), locals),
+                                None => self.enter_fn_unchecked(key.clone(), locals),
+                            }
+                        }
+                        // Refused by check_access, which raised.
+                        Some(_) => (),
+                    }
                 },
-                Instr::Ret => {
-                    let (return_value, return_address) =  {
+                Instr::CallMethod(ref fn_name, num_args) => {
+                    let fn_name = fn_name.to_string();
+                    let args = self.frames.last_mut().unwrap().pop_n(num_args + 1);
+                    self.call_method(fn_name, args);
+                },
+                Instr::CallBuiltin(ref fn_name, num_args) => {
+                    let fn_name = fn_name.to_string();
+                    let args = self.frames.last_mut().unwrap().pop_n(num_args);
+                    let result = self.call_builtin(&fn_name, args);
+                    let frame = self.frames.last_mut().unwrap();
+                    match result {
+                        Ok(x) => frame.push(x),
+                        Err(msg) => frame.raise(&msg),
+                    }
+                    self.pc += 1
+                },
+                Instr::CallValue(num_args) => {
+                    let (callee, args) = {
                         let frame = self.frames.last_mut().unwrap();
-                        let ret_val = if frame.stack.len() > 0 {
-                            frame.pop()
+                        let args = frame.pop_n(num_args);
+                        (frame.pop(), args)
+                    };
+                    self.call_value(callee, args);
+                },
+                Instr::Bind(ref class_name, ref fn_name) => {
+                    let value = self.frames.last_mut().unwrap().pop();
+                    self.bindings.insert((class_name.to_string(), fn_name.to_string()), value);
+                    self.pc += 1
+                },
+                Instr::GetIter => {
+                    let iterable = self.frames.last_mut().unwrap().pop();
+                    self.get_iter(iterable);
+                },
+                Instr::IterNext => {
+                    let iterator = self.frames.last_mut().unwrap().pop();
+                    match iterator {
+                        NativeType::Iterator(x) => {
+                            let value = self.iterators[x].next();
+                            self.frames.last_mut().unwrap().push(value);
+                            self.pc += 1
+                        }
+                        NativeType::Generator(x) => self.resume(x, NativeType::NoneType, true),
+                        obj => {
+                            let depth = self.frames.len();
+                            self.call_method(NEXT_FN.to_string(), vec![obj]);
+                            if self.frames.len() > depth {
+                                self.frames.last_mut().unwrap().iterating = true;
+                            }
+                        }
+                    }
+                },
+                Instr::Yield => {
+                    let mut frame = self.frames.pop().unwrap();
+                    let value = frame.pop();
+                    match frame.generator {
+                        Some(gen) => {
+                            let return_address = frame.return_address;
+                            self.generators[gen].pc = self.pc + 1;
+                            self.generators[gen].frame = Some(frame);
+                            self.frames.last_mut().unwrap().push(value);
+                            self.pc = return_address;
                         }
-                        else {
-                            NativeType::NoneType
+                        None => {
+                            self.frames.push(frame);
+                            self.frames.last_mut().unwrap()
+                                .raise("SyntaxError: yield outside of a generator");
+                        }
+                    }
+                },
+                Instr::Ret => {
+                    if let Some(msg) = self.return_type_error() {
+                        self.frames.last_mut().unwrap().raise(&msg);
+                    }
+                    else {
+                        let (return_value, return_address) =  {
+                            let frame = self.frames.last_mut().unwrap();
+                            let ret_val = if frame.stack.len() > 0 {
+                                frame.pop()
+                            }
+                            else {
+                                NativeType::NoneType
+                            };
+                            (ret_val, frame.return_address)
                         };
-                        (ret_val, frame.return_address)
-                    };
-                    self.frames.pop();
-                    let frame = self.frames.last_mut().unwrap();
-                    frame.push(return_value);
-                    self.pc = return_address;
+                        let returning = self.frames.pop().unwrap();
+                        let return_value = match (returning.generator, return_value) {
+                            // Running off the end of a generator exhausts it.
+                            (Some(gen), _) => {
+                                self.generators[gen].finished = true;
+                                if returning.iterating { NativeType::Exhausted } else { NativeType::NoneType }
+                            }
+                            // An iterator object's `next` returns None once
+                            // it has run out.
+                            (None, NativeType::NoneType) if returning.iterating => NativeType::Exhausted,
+                            (None, x) => x
+                        };
+                        let frame = self.frames.last_mut().unwrap();
+                        frame.push(return_value);
+                        self.pc = return_address;
+                    }
                 },
                 Instr::Exit => {
                     let frame = self.frames.last_mut().unwrap();
@@ -246,19 +713,475 @@ impl VM {
                 _ => panic!("InstrNotImplemented"),
             };
             self.unwind_stack_on_raise();
+            if self.frames.len() <= stop_depth {
+                break
+            }
         }
         result
     }
 
+    // Enters the function after checking `args` against its parameters'
+    // annotated types.
+    fn enter_fn(&mut self, key: (String, String), args: Vec<NativeType>) {
+        match self.arg_type_error(&key, &args) {
+            Some(msg) => self.frames.last_mut().unwrap().raise(&msg),
+            None => self.enter_fn_unchecked(key, args)
+        }
+    }
+
+    fn arg_type_error(&self, key: &(String, String), args: &[NativeType]) -> Option<String> {
+        let fn_meta = self.bytecode.symbols.get(key).unwrap();
+        for (i, arg) in args.iter().enumerate() {
+            if let Some(expected) = fn_meta.param_type(i) {
+                if !self.has_type(arg, expected) {
+                    return Some(format!("TypeError: parameter {} of {} expects {}, got {}",
+                                        fn_meta.param_name(i), qualified_name(&key.0, &key.1), expected,
+                                        self.type_of(arg)))
+                }
+            }
+        }
+        None
+    }
+
+    // Checks the value being returned from the current frame against the
+    // function's annotated result type.
+    fn return_type_error(&self) -> Option<String> {
+        let frame = self.frames.last().unwrap();
+        if frame.generator.is_some() {
+            return None
+        }
+        let key = (frame.class.to_string(), frame.name.to_string());
+        let expected = self.bytecode.symbols.get(&key)?.checked_return_type()?;
+        let value = frame.stack.last().cloned().unwrap_or(NativeType::NoneType);
+        if self.has_type(&value, expected) {
+            return None
+        }
+        Some(format!("TypeError: {} should return {}, got {}",
+                     qualified_name(&key.0, &key.1), expected, self.type_of(&value)))
+    }
+
+    // The name of `value`'s type, as written in annotations.
+    fn type_of(&self, value: &NativeType) -> String {
+        match *value {
+            NativeType::NoneType => "None".to_string(),
+            NativeType::Variant(ref enum_name, ..) => enum_name.to_string(),
+            ref x => self.class_of(x),
+        }
+    }
+
+    fn has_type(&self, value: &NativeType, type_name: &str) -> bool {
+        let actual = self.type_of(value);
+        actual == type_name
+            || self.bytecode.ancestors(&actual).iter().any(|c| c == type_name)
+            || self.bytecode.implements(&actual, type_name)
+    }
+
+    // Pushes a frame for `key` and jumps to it. Generator functions instead
+    // get a suspended frame wrapped in a new generator, which is pushed as
+    // the result of the call.
+    fn enter_fn_unchecked(&mut self, key: (String, String), args: Vec<NativeType>) {
+        let label = self.bytecode.labels.get(&key).unwrap().clone();
+        let mut new_frame = Frame::new(key.1.to_string(), args, self.pc + 1);
+        new_frame.class = key.0.to_string();
+        if self.bytecode.symbols.get(&key).unwrap().is_generator() {
+            new_frame.generator = Some(self.generators.len());
+            self.generators.push(Generator {
+                frame: Some(new_frame),
+                pc: label,
+                started: false,
+                finished: false,
+            });
+            let gen_ref = self.generators.len() - 1;
+            self.frames.last_mut().unwrap().push(NativeType::Generator(gen_ref));
+            self.pc += 1
+        }
+        else {
+            self.frames.push(new_frame);
+            self.pc = label
+        }
+    }
+
+    // Reattaches a generator's frame and continues it from where it last
+    // yielded. `sent` becomes the value of that yield expression; it is
+    // ignored on the first resume, as there's no yield waiting for it. An
+    // exhausted generator produces None, or Exhausted when `iterating` for a
+    // for loop.
+    fn resume(&mut self, gen: usize, sent: NativeType, iterating: bool) {
+        if self.generators[gen].finished {
+            let end = if iterating { NativeType::Exhausted } else { NativeType::NoneType };
+            self.frames.last_mut().unwrap().push(end);
+            self.pc += 1;
+            return
+        }
+        let mut frame = match self.generators[gen].frame.take() {
+            Some(x) => x,
+            None => {
+                self.frames.last_mut().unwrap()
+                    .raise("ValueError: generator already executing");
+                return
+            }
+        };
+        if self.generators[gen].started {
+            frame.push(sent);
+        }
+        self.generators[gen].started = true;
+        frame.return_address = self.pc + 1;
+        frame.iterating = iterating;
+        self.frames.push(frame);
+        self.pc = self.generators[gen].pc;
+    }
+
+    // Calls `fn_name` on the class of `args[0]`, which is passed to the method
+    // as its first parameter unless the method is static. The result is pushed
+    // onto the caller's stack when the method returns.
+    fn call_method(&mut self, fn_name: String, mut args: Vec<NativeType>) {
+        if let NativeType::Generator(gen) = args[0] {
+            match (fn_name.as_ref(), args.len()) {
+                ("next", 1) => self.resume(gen, NativeType::NoneType, false),
+                ("send", 2) => self.resume(gen, args[1].clone(), false),
+                _ => {
+                    let msg = format!("AttributeError: Generator has no method {}", fn_name);
+                    self.frames.last_mut().unwrap().raise(&msg);
+                }
+            }
+            return
+        }
+        let class_name = match args[0] {
+            NativeType::ObjectRef(x) => self.heap[x].class.to_string(),
+            ref x => x.type_name().to_string(),
+        };
+        let key = match self.bytecode.lookup_method(&class_name, &fn_name) {
+            Some(x) => x,
+            None => {
+                // A field holding a callable value can be called like a
+                // method, but isn't passed the receiver.
+                if let NativeType::ObjectRef(x) = args[0] {
+                    if let Some(callee) = self.heap[x].fields.get(&fn_name).cloned() {
+                        if self.check_field_access(x, &fn_name) {
+                            args.remove(0);
+                            self.call_value(callee, args);
+                        }
+                        return
+                    }
+                }
+                let msg = format!("AttributeError: {} has no method {}", class_name, fn_name);
+                self.frames.last_mut().unwrap().raise(&msg);
+                return
+            }
+        };
+        if !self.check_access(&key.0, &key.1) {
+            return
+        }
+        let params_len = {
+            let fn_metadata = self.bytecode.symbols.get(&key).unwrap();
+            if fn_metadata.is_static() {
+                args.remove(0);
+            }
+            fn_metadata.params_len()
+        };
+        if let Some(callee) = self.bindings.get(&key).cloned() {
+            return self.call_value(callee, args)
+        }
+        if params_len != args.len() {
+            let msg = format!("TypeError: {}.{} takes {} arguments, got {}",
+                              key.0, key.1, params_len, args.len());
+            self.frames.last_mut().unwrap().raise(&msg);
+            return
+        }
+        self.enter_fn(key, args);
+    }
+
+    // Calls a function value, or an object with a `__call__` method, which is
+    // passed the object followed by `args`.
+    fn call_value(&mut self, callee: NativeType, args: Vec<NativeType>) {
+        match callee {
+            NativeType::Function(class_name, fn_name) => {
+                let key = (class_name, fn_name);
+                if !self.check_access(&key.0, &key.1) {
+                    return
+                }
+                let params_len = self.bytecode.symbols.get(&key).unwrap().params_len();
+                if params_len != args.len() {
+                    let msg = format!("TypeError: {}.{} takes {} arguments, got {}",
+                                      key.0, key.1, params_len, args.len());
+                    self.frames.last_mut().unwrap().raise(&msg);
+                    return
+                }
+                self.enter_fn(key, args);
+            }
+            NativeType::ObjectRef(x) if self.bytecode.lookup_method(&self.heap[x].class, CALL_FN).is_some() => {
+                let mut method_args = vec![callee];
+                method_args.extend(args);
+                self.call_method(CALL_FN.to_string(), method_args);
+            }
+            x => {
+                let msg = format!("TypeError: {} is not callable", self.class_of(&x));
+                self.frames.last_mut().unwrap().raise(&msg);
+            }
+        }
+    }
+
+    // Binary operators on objects call the left operand's special method for
+    // the operator, such as `__add__`, with the right operand as its
+    // argument. Returns false if there's no such method, in which case the
+    // operator is handled as usual.
+    fn call_operator(&mut self, method: &str) -> bool {
+        let lhs = {
+            let ref stack = self.frames.last().unwrap().stack;
+            stack[stack.len() - 2].clone()
+        };
+        if let NativeType::ObjectRef(x) = lhs {
+            if self.bytecode.lookup_method(&self.heap[x].class, method).is_some() {
+                let args = self.frames.last_mut().unwrap().pop_n(2);
+                self.call_method(method.to_string(), args);
+                return true
+            }
+        }
+        false
+    }
+
+    // Shows a value as `NativeType::pretty` does, except that objects with a
+    // `__str__` method are shown as it describes them. Fails with the
+    // exception a `__str__` method raises, after which no others are called.
+    fn pretty(&mut self, value: &NativeType) -> Result<String, String> {
+        let shown = value.pretty_with(&mut |x| {
+            if self.exception.is_some() { None } else { self.object_str(x) }
+        });
+        match self.exception.take() {
+            Some(msg) => Err(msg),
+            None => Ok(shown)
+        }
+    }
+
+    fn object_str(&mut self, obj: usize) -> Option<String> {
+        let key = self.bytecode.lookup_method(&self.heap[obj].class, STR_FN)?;
+        self.call_sync(key, vec![NativeType::ObjectRef(obj)]).map(|x| x.pretty())
+    }
+
+    // Private members can only be accessed by code in the class declaring
+    // them. Raises an AccessError in the current frame and returns false
+    // otherwise.
+    fn check_access(&mut self, owner: &str, member: &str) -> bool {
+        match self.access_error(owner, member) {
+            Some(msg) => {
+                self.frames.last_mut().unwrap().raise(&msg);
+                false
+            }
+            None => true
+        }
+    }
+
+    fn check_field_access(&mut self, obj: usize, field_name: &str) -> bool {
+        match self.field_access_error(obj, field_name) {
+            Some(msg) => {
+                self.frames.last_mut().unwrap().raise(&msg);
+                false
+            }
+            None => true
+        }
+    }
+
+    fn access_error(&self, owner: &str, member: &str) -> Option<String> {
+        if self.bytecode.is_private(owner, member) && self.frames.last().unwrap().class != owner {
+            return Some(format!("AccessError: {}.{} is private", owner, member))
+        }
+        None
+    }
+
+    fn field_access_error(&self, obj: usize, field_name: &str) -> Option<String> {
+        let owner = self.bytecode.field_owner(&self.heap[obj].class, field_name)?;
+        self.access_error(&owner, field_name)
+    }
+
+    fn call_builtin(&mut self, fn_name: &str, args: Vec<NativeType>) -> Result<NativeType, String> {
+        match fn_name {
+            "range" => {
+                let mut bounds = Vec::new();
+                for arg in args.iter() {
+                    match *arg {
+                        NativeType::Int(x) => bounds.push(x),
+                        _ => return Err("TypeError: range expects Int arguments".to_string()),
+                    }
+                }
+                let (start, stop, step) = match bounds.len() {
+                    1 => (0, bounds[0], 1),
+                    2 => (bounds[0], bounds[1], 1),
+                    3 => (bounds[0], bounds[1], bounds[2]),
+                    _ => return Err("TypeError: range expects 1 to 3 arguments".to_string()),
+                };
+                if step == 0 {
+                    return Err("ValueError: range step must not be zero".to_string());
+                }
+                Ok(NativeType::Range(start, stop, step))
+            }
+            "implements" => {
+                expect_args(fn_name, &args, 2)?;
+                match (&args[0], &args[1]) {
+                    (&NativeType::ObjectRef(x), &NativeType::Trait(ref t)) => {
+                        Ok(NativeType::Bool(self.bytecode.implements(&self.heap[x].class, t)))
+                    }
+                    (_, &NativeType::Trait(_)) => Ok(NativeType::Bool(false)),
+                    _ => Err("TypeError: implements expects a trait".to_string()),
+                }
+            }
+            "type" => {
+                expect_args(fn_name, &args, 1)?;
+                Ok(NativeType::Class(self.class_of(&args[0])))
+            }
+            "isinstance" => {
+                expect_args(fn_name, &args, 2)?;
+                match args[1] {
+                    NativeType::Class(ref cls) => {
+                        let class_name = self.class_of(&args[0]);
+                        let found = class_name == *cls
+                            || self.bytecode.ancestors(&class_name).iter().any(|c| c == cls);
+                        Ok(NativeType::Bool(found))
+                    }
+                    _ => Err("TypeError: isinstance expects a class".to_string()),
+                }
+            }
+            "getattr" => {
+                expect_args(fn_name, &args, 2)?;
+                let (obj, name) = object_and_name(fn_name, &args)?;
+                self.field_access_error(obj, &name).map_or(Ok(()), Err)?;
+                match self.heap[obj].fields.get(&name) {
+                    Some(x) => Ok(x.clone()),
+                    None => Err(format!("AttributeError: {} has no field {}", self.heap[obj].class, name)),
+                }
+            }
+            "setattr" => {
+                expect_args(fn_name, &args, 3)?;
+                let (obj, name) = object_and_name(fn_name, &args)?;
+                self.field_access_error(obj, &name).map_or(Ok(()), Err)?;
+                self.heap[obj].fields.insert(name, args[2].clone());
+                Ok(NativeType::NoneType)
+            }
+            "hasattr" => {
+                expect_args(fn_name, &args, 2)?;
+                let (obj, name) = object_and_name(fn_name, &args)?;
+                Ok(NativeType::Bool(self.heap[obj].fields.contains_key(&name)))
+            }
+            "fields" => {
+                expect_args(fn_name, &args, 1)?;
+                match args[0] {
+                    NativeType::ObjectRef(x) => {
+                        let mut names: Vec<&String> = self.heap[x].fields.keys().collect();
+                        names.sort();
+                        Ok(NativeType::Tuple(names.iter().map(|n| NativeType::Str(n.to_string())).collect()))
+                    }
+                    ref x => Err(format!("TypeError: {} has no fields", x.type_name())),
+                }
+            }
+            "methods" => {
+                expect_args(fn_name, &args, 1)?;
+                match args[0] {
+                    NativeType::Class(ref cls) => {
+                        let mut names = self.method_names(cls);
+                        names.sort();
+                        names.dedup();
+                        Ok(NativeType::Tuple(names.into_iter().map(NativeType::Str).collect()))
+                    }
+                    _ => Err("TypeError: methods expects a class".to_string()),
+                }
+            }
+            _ => panic!("Unknown builtin {}", fn_name)
+        }
+    }
+
+    // The name of a value's class. Values other than objects belong to the
+    // class named after their type.
+    fn class_of(&self, value: &NativeType) -> String {
+        match *value {
+            NativeType::ObjectRef(x) => self.heap[x].class.to_string(),
+            ref x => x.type_name().to_string(),
+        }
+    }
+
+    // The names of the methods which can be called on instances of `class`,
+    // including inherited ones. Methods generated by the compiler aren't
+    // included.
+    fn method_names(&self, class: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut classes = self.bytecode.ancestors(class);
+        classes.insert(0, class.to_string());
+        for &(ref c, ref m) in self.bytecode.symbols.keys() {
+            if classes.contains(c) && !m.starts_with('%') {
+                names.push(m.to_string());
+            }
+        }
+        names
+    }
+
+    // Pushes an iterator over `iterable`, which may be a tuple, string,
+    // range, iterator or generator. The language has no list or map values,
+    // so there's no iteration over them. For user objects this calls their
+    // `iter` method, or uses the object itself if it only defines `next`.
+    fn get_iter(&mut self, iterable: NativeType) {
+        let iter = match iterable {
+            NativeType::Tuple(elems) => Iter::Seq(elems, 0),
+            NativeType::Str(ref x) => {
+                Iter::Seq(x.chars().map(|c| NativeType::Str(c.to_string())).collect(), 0)
+            }
+            NativeType::Range(start, stop, step) => Iter::Range(start, stop, step),
+            NativeType::Iterator(_) | NativeType::Generator(_) => {
+                self.frames.last_mut().unwrap().push(iterable);
+                self.pc += 1;
+                return
+            }
+            NativeType::ObjectRef(x) => {
+                let class_name = self.heap[x].class.to_string();
+                let has_method = |name: &str| {
+                    self.bytecode.lookup_method(&class_name, name).is_some()
+                };
+                if has_method(ITER_FN) {
+                    self.call_method(ITER_FN.to_string(), vec![iterable]);
+                }
+                else if has_method(NEXT_FN) {
+                    self.frames.last_mut().unwrap().push(iterable);
+                    self.pc += 1;
+                }
+                else {
+                    let msg = format!("TypeError: {} is not iterable", class_name);
+                    self.frames.last_mut().unwrap().raise(&msg);
+                }
+                return
+            }
+            x => {
+                let msg = format!("TypeError: {} is not iterable", x.type_name());
+                self.frames.last_mut().unwrap().raise(&msg);
+                return
+            }
+        };
+        self.iterators.push(iter);
+        let iter_ref = self.iterators.len() - 1;
+        self.frames.last_mut().unwrap().push(NativeType::Iterator(iter_ref));
+        self.pc += 1
+    }
+
     fn enter_main(&mut self) {
-        self.pc = self.bytecode.labels.get(
-            &(GLOBAL_NSPACE.to_string(), MAIN_FN.to_string()))
+        let end = self.bytecode.bytecode.len();
+        let entry = match self.bytecode.entry.clone() {
+            Some(entry) => {
+                // Other entry points end with a return rather than an exit,
+                // so they need a frame to return their result to.
+                self.frames.push(Frame::new("main".to_string(), Vec::new(), end));
+                entry
+            }
+            None => (GLOBAL_NSPACE.to_string(), MAIN_FN.to_string())
+        };
+        self.pc = self.bytecode.labels.get(&entry)
             .expect("Main method not found").clone();
-        self.frames.push(Frame::new("main".to_string(), Vec::new(), self.bytecode.bytecode.len()))
+        let mut frame = Frame::new(entry.1.to_string(), Vec::new(), end);
+        frame.class = entry.0;
+        self.frames.push(frame)
     }
 
     fn unwind_stack_on_raise(&mut self) {
         if self.frames.last().unwrap().raise {
+            let msg = match self.frames.last().unwrap().stack.last() {
+                Some(x) => x.pretty(),
+                None => "Exception".to_string(),
+            };
             let mut backtrace: Vec<NativeType> = Vec::new();
             let mut try_index: usize = self.frames.len() - 1;
             for (i, f) in self.frames.iter().rev().enumerate() {
@@ -275,8 +1198,9 @@ impl VM {
             match self.frames.last() {
                 Some(ref x) => self.pc = x.return_address, //FIXME: WRONG
                 None => {
-                    eprintln!("Exception raised. Backtrace:");
+                    eprintln!("Exception raised: {}. Backtrace:", msg);
                     eprintln!("{:?}", backtrace);
+                    self.exception = Some(msg);
                     self.pc = usize::max_value()
                 }
             }
@@ -290,7 +1214,13 @@ struct Frame {
     return_address: usize,
     raise: bool,
     in_try: bool,
-    name: String
+    name: String,
+    // The class whose code is running in this frame.
+    class: String,
+    generator: Option<usize>,
+    // Whether the frame is producing the next value for a for loop, in which
+    // case finishing without one ends the loop.
+    iterating: bool,
 }
 
 impl Frame {
@@ -301,7 +1231,10 @@ impl Frame {
             return_address: return_address,
             raise: false,
             in_try: false,
-            name: name
+            name: name,
+            class: GLOBAL_NSPACE.to_string(),
+            generator: None,
+            iterating: false,
         }
     }
 
@@ -316,6 +1249,12 @@ impl Frame {
         }
     }
 
+    // Pops the top `n` values, returning them in the order they were pushed.
+    fn pop_n(&mut self, n: usize) -> Vec<NativeType> {
+        let start = self.stack.len() - n;
+        self.stack.split_off(start)
+    }
+
     fn dup(&mut self) {
         let tos = self.peek().unwrap().clone();
         self.push(tos)
@@ -332,14 +1271,12 @@ impl Frame {
 
     fn store_local(&mut self, index: usize) {
         let value = self.pop();
-        let len = self.locals.len();
-        if index < len {
-            self.locals[index] = value;
-        }
-        else {
-            assert_eq!(index, len);
-            self.locals.push(value)
+        // Locals are numbered in the order they appear in the source, but a
+        // branch which isn't taken can skip the first store to some of them.
+        if index >= self.locals.len() {
+            self.locals.resize(index + 1, NativeType::NoneType);
         }
+        self.locals[index] = value;
     }
 
     fn raise(&mut self, msg: &str) {
@@ -348,6 +1285,50 @@ impl Frame {
         self.raise = true
     }
 
+    fn build_tuple(&mut self, len: usize) {
+        let elems = self.pop_n(len);
+        self.push(NativeType::Tuple(elems))
+    }
+
+    fn index(&mut self) {
+        let index = self.pop();
+        let value = self.pop();
+        match (value, index) {
+            (NativeType::Tuple(ref x), NativeType::Int(i)) => {
+                match x.get(i as usize) {
+                    Some(elem) if i >= 0 => self.push(elem.clone()),
+                    _ => self.raise("IndexError: tuple index out of range"),
+                }
+            }
+            (NativeType::Str(ref x), NativeType::Int(i)) => {
+                match x.chars().nth(i as usize) {
+                    Some(c) if i >= 0 => self.push(NativeType::Str(c.to_string())),
+                    _ => self.raise("IndexError: string index out of range"),
+                }
+            }
+            _ => self.raise("TypeError"),
+        }
+    }
+
+    fn unpack(&mut self, len: usize) {
+        match self.pop() {
+            NativeType::Tuple(elems) => {
+                if elems.len() == len {
+                    self.stack.extend(elems)
+                }
+                else {
+                    let msg = format!("ValueError: expected {} values to unpack, got {}",
+                                      len, elems.len());
+                    self.raise(&msg)
+                }
+            }
+            x => {
+                let msg = format!("TypeError: cannot unpack {}", x.type_name());
+                self.raise(&msg)
+            }
+        }
+    }
+
     fn add(&mut self) {
         let rhs = self.pop();
         let lhs = self.pop();
@@ -423,21 +1404,62 @@ impl Frame {
     pub fn eq(&mut self) {
         let rhs = self.pop();
         let lhs = self.pop();
-        match (lhs, rhs) {
-            (NativeType::Int(x), NativeType::Int(y))        => self.push(NativeType::Bool(x==y)),
-            (NativeType::Int(x), NativeType::Double(y))     => self.push(NativeType::Bool(x as f32 == y)),
-            (NativeType::Double(x), NativeType::Int(y))     => self.push(NativeType::Bool(x == (y as f32))),
-            (NativeType::Double(x), NativeType::Double(y))  => self.push(NativeType::Bool(x==y)),
-            _ => self.raise("TypeError"),
+        match lhs.equals(&rhs) {
+            Some(x) => self.push(NativeType::Bool(x)),
+            None => self.raise("TypeError"),
         }
     }
+
+    // A literal pattern just doesn't match a value of another type.
+    fn match_eq(&mut self) {
+        let rhs = self.pop();
+        let lhs = self.pop();
+        self.push(NativeType::Bool(lhs.equals(&rhs).unwrap_or(false)))
+    }
+}
+
+// Sequences of different lengths are unequal. Otherwise they're equal if
+// each pair of elements is, and incomparable if any pair is.
+fn all_equal(x: &[NativeType], y: &[NativeType]) -> Option<bool> {
+    if x.len() != y.len() {
+        return Some(false)
+    }
+    let mut equal = true;
+    for (a, b) in x.iter().zip(y.iter()) {
+        equal &= a.equals(b)?;
+    }
+    Some(equal)
+}
+
+fn expect_args(fn_name: &str, args: &Vec<NativeType>, len: usize) -> Result<(), String> {
+    if args.len() != len {
+        return Err(format!("TypeError: {} expects {} arguments, got {}", fn_name, len, args.len()))
+    }
+    Ok(())
+}
+
+// Builtins such as `getattr` take an object followed by a field name.
+fn object_and_name(fn_name: &str, args: &Vec<NativeType>) -> Result<(usize, String), String> {
+    match (&args[0], &args[1]) {
+        (&NativeType::ObjectRef(x), &NativeType::Str(ref name)) => Ok((x, name.to_string())),
+        _ => Err(format!("TypeError: {} expects an object and a field name", fn_name)),
+    }
 }
 
 pub fn run(bytecode: Bytecode) -> String {
+    try_run(bytecode).unwrap_or_default()
+}
+
+// Runs the program, returning its result, or the message of the exception
+// which stopped it.
+pub fn try_run(bytecode: Bytecode) -> Result<String, String> {
     let mut vm = VM::new(bytecode);
     let res = vm.run();
+    if let Some(msg) = vm.exception.take() {
+        return Err(msg)
+    }
     match res {
-        Some(ref x) => x.pretty(),
-        None => "".to_string(),
+        Some(ref x) => vm.pretty(x),
+        None => Ok("".to_string()),
     }
 }
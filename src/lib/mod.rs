@@ -1,9 +1,18 @@
 #![feature(try_from)]
 
+#[macro_use]
 extern crate lrpar;
+#[macro_use]
 extern crate lrlex;
 extern crate lrtable;
 extern crate cfgrammar;
+extern crate toml;
+extern crate bincode;
 
+pub mod ast;
 pub mod interp;
+pub mod module;
 pub mod parse;
+pub mod project;
+pub mod resolve;
+pub mod typeck;
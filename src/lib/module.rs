@@ -0,0 +1,211 @@
+// A program can be split into modules, one per source file, with `foo.plang`
+// holding the module `foo`. Imported modules are looked for next to the
+// module importing them, then on the search path. Each module is compiled
+// once, after the modules it imports, and linked into a single Bytecode in
+// which the classes, traits and enums declared by `foo` are named `foo.Name`,
+// and its global functions belong to the class `foo.global`. The main
+// module's names are left as they are.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ast;
+use parse::{self, Bytecode, CompileOptions, Grammar, Imports, ParseError, read_file};
+
+static EXTENSION: &'static str = "plang";
+
+struct Module {
+    prefix: String,
+    // The names the module declares, and what they're called once linked.
+    names: HashMap<String, String>,
+    // The module's global functions.
+    functions: Vec<String>,
+}
+
+impl Module {
+    fn global_class(&self) -> String {
+        qualify(&self.prefix, "global")
+    }
+}
+
+struct Loader<'a> {
+    grammar: Grammar<'a>,
+    options: &'a CompileOptions,
+    program: Bytecode,
+    modules: HashMap<PathBuf, Module>,
+    // The modules currently being loaded, each imported by the one before
+    // it, so that import cycles can be reported in full.
+    loading: Vec<(String, PathBuf)>,
+}
+
+pub fn load_program(path: &Path, grammar: Grammar, options: &CompileOptions) -> Result<Bytecode, ParseError> {
+    let source = read_file(path)?;
+    let mut loader = Loader::new(grammar, options);
+    let name = path.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+    loader.loading.push((name, canonical(path)));
+    loader.compile("", &source, path.parent())?;
+    loader.set_entry()?;
+    Ok(loader.program)
+}
+
+pub fn load_source(source: String, grammar: Grammar, options: &CompileOptions) -> Result<Bytecode, ParseError> {
+    let mut loader = Loader::new(grammar, options);
+    loader.compile("", &source, None)?;
+    loader.set_entry()?;
+    Ok(loader.program)
+}
+
+impl<'a> Loader<'a> {
+    fn new(grammar: Grammar<'a>, options: &'a CompileOptions) -> Loader<'a> {
+        Loader {
+            grammar: grammar,
+            options: options,
+            program: Bytecode::default(),
+            modules: HashMap::new(),
+            loading: Vec::new(),
+        }
+    }
+
+    fn load(&mut self, name: &str, path: PathBuf) -> Result<(), ParseError> {
+        if self.modules.contains_key(&path) {
+            return Ok(())
+        }
+        if self.loading.iter().any(|&(_, ref p)| *p == path) {
+            let mut chain: Vec<&str> = self.loading.iter().map(|&(ref n, _)| n.as_ref()).collect();
+            chain.push(name);
+            return Err(ParseError::ImportError(format!("import cycle: {}", chain.join(" -> "))))
+        }
+        self.loading.push((name.to_string(), path.clone()));
+        let source = read_file(&path)?;
+        let module = self.compile(name, &source, path.parent())?;
+        self.loading.pop();
+        // Modules are linked under their names, so two files with the same
+        // name would share their declarations' names.
+        if let Some(other) = self.modules.iter().find(|&(_, m)| m.prefix == module.prefix).map(|(p, _)| p) {
+            return Err(ParseError::ImportError(format!("{} and {} are both imported as {}",
+                                                       other.display(), path.display(), name)))
+        }
+        self.modules.insert(path, module);
+        Ok(())
+    }
+
+    // Compiles a module, after the modules it imports, and links it into the
+    // program.
+    fn compile(&mut self, prefix: &str, source: &str, dir: Option<&Path>) -> Result<Module, ParseError> {
+        let (grm, pt) = parse::parse_tree(source, self.grammar)?;
+        let prog = ast::build(&pt, grm, source);
+        let mut imports = Imports::default();
+        // The names the module uses for imported declarations, and what
+        // they're called once linked.
+        let mut names = HashMap::new();
+        for import in parse::imports(&prog) {
+            let path = self.find(&import.module, dir)?;
+            self.load(&import.module, path.clone())?;
+            let module = &self.modules[&path];
+            // A module's global functions are called through its name.
+            if import.names.is_empty() {
+                let global = module.global_class();
+                if let Some(class) = self.program.classes.get(&global) {
+                    imports.classes.insert(import.module.clone(), class.clone());
+                }
+                names.insert(import.module.clone(), global);
+            }
+            for name in import.names {
+                if module.functions.contains(&name) {
+                    imports.functions.insert(name, module.global_class());
+                    continue
+                }
+                let linked = match module.names.get(&name) {
+                    Some(x) => x.clone(),
+                    None => return Err(ParseError::ImportError(
+                        format!("module {} has no {}", import.module, name)))
+                };
+                if let Some(class) = self.program.classes.get(&linked) {
+                    imports.classes.insert(name.clone(), class.clone());
+                }
+                if self.program.traits.contains(&linked) {
+                    imports.traits.push(name.clone());
+                }
+                if let Some(variants) = self.program.enums.get(&linked) {
+                    imports.enums.insert(name.clone(), variants.clone());
+                }
+                names.insert(name, linked);
+            }
+        }
+        // Declarations already linked are known by their qualified names, so
+        // that the ancestors of imported classes can be found.
+        for (name, class) in self.program.classes.iter() {
+            imports.classes.entry(name.clone()).or_insert_with(|| class.clone());
+        }
+        imports.traits.extend(self.program.traits.iter().cloned());
+        for (name, variants) in self.program.enums.iter() {
+            imports.enums.entry(name.clone()).or_insert_with(|| variants.clone());
+        }
+        // Functions are counted under the names the module uses for their
+        // classes, as well as their qualified names.
+        for (&(ref class, ref function), f) in self.program.symbols.iter() {
+            imports.arities.insert((class.clone(), function.clone()), f.params_len());
+            for (name, _) in names.iter().filter(|&(_, linked)| linked == class) {
+                imports.arities.insert((name.clone(), function.clone()), f.params_len());
+            }
+        }
+        imports.names = names.clone();
+
+        let bytecode = parse::compile(&prog, source, self.options, &imports)?;
+        let mut own = HashMap::new();
+        {
+            let declared = bytecode.classes.keys().filter(|c| !imports.classes.contains_key(*c))
+                .chain(bytecode.traits.iter().filter(|t| !imports.traits.contains(t)))
+                .chain(bytecode.enums.keys().filter(|e| !imports.enums.contains_key(*e)));
+            for name in declared {
+                own.insert(name.clone(), qualify(prefix, name));
+            }
+        }
+        own.insert("global".to_string(), qualify(prefix, "global"));
+        let functions = bytecode.symbols.keys()
+            .filter(|&&(ref c, ref f)| c == "global" && !f.starts_with('%'))
+            .map(|&(_, ref f)| f.clone())
+            .collect();
+        names.extend(own.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.program.link(bytecode, &names, prefix, &imports);
+        Ok(Module { prefix: prefix.to_string(), names: own, functions: functions })
+    }
+
+    // The program can start in any global function or static method which
+    // takes no arguments.
+    fn set_entry(&mut self) -> Result<(), ParseError> {
+        if let Some((ref class, ref method)) = self.options.entry {
+            let key = (class.clone(), method.clone());
+            match self.program.symbols.get(&key) {
+                Some(f) if (class == "global" || f.is_static()) && f.params_len() == 0 => (),
+                Some(_) => return Err(ParseError::ProjectError(
+                    format!("entry point {}.{} must be static and take no arguments", class, method))),
+                None => return Err(ParseError::ProjectError(
+                    format!("entry point {}.{} not found", class, method)))
+            }
+            self.program.entry = Some(key);
+        }
+        Ok(())
+    }
+
+    // Looks for `name` next to the importing module, then on the search
+    // path.
+    fn find(&self, name: &str, dir: Option<&Path>) -> Result<PathBuf, ParseError> {
+        let file_name = format!("{}.{}", name, EXTENSION);
+        dir.into_iter()
+            .chain(self.options.search_path.iter().map(|p| p.as_path()))
+            .map(|d| d.join(&file_name))
+            .find(|p| p.is_file())
+            .map(|p| canonical(&p))
+            .ok_or_else(|| ParseError::ImportError(format!("cannot find module {}", name)))
+    }
+}
+
+fn qualify(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() { name.to_string() } else { format!("{}.{}", prefix, name) }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
@@ -3,25 +3,53 @@ extern crate lrlex;
 extern crate lrtable;
 extern crate cfgrammar;
 
-use std::path::Path;
-use std::fs::File;
-use std::io::Read;
-use std::convert::{TryFrom, TryInto};
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::convert::TryFrom;
 use std::collections::HashMap;
+use std::fmt;
+use std::process;
 
 use self::lrpar::parser;
 use self::lrpar::parser::Node;
-use self::lrlex::{build_lex};
-use self::lrtable::{Minimiser, from_yacc};
+use self::lrlex::{build_lex, LexerDef, Lexeme};
+use self::lrtable::{Minimiser, StateGraph, StateTable, from_yacc};
 
-use self::cfgrammar::TIdx;
+use self::cfgrammar::{NTIdx, TIdx};
 use self::cfgrammar::yacc::{yacc_grm, YaccGrammar, YaccKind};
 
+use ast;
+use ast::{BinOp, ClassDef, ConstDecl, Decl, EnumDef, Expr, ExprKind, FuncDef, Ident, Literal, MatchArm,
+          MemberKind, Param, Pattern, PatternKind, Program, Span, Stmt, StmtKind, Target, TraitDef,
+          TraitMethod};
+use interp::is_builtin;
+use module;
+use resolve;
+use typeck;
+use typeck::TypeInfo;
+
 // This can be arbitrary, ultimately it doesn't matter what the placeholder's
 // value is, because it is switched out almost immediately.
 const PLACEHOLDER: usize = usize::max_value();
 
-static CONSTRUCTOR: &'static str = "construct";
+pub static CONSTRUCTOR: &'static str = "construct";
+// Inside a constructor, the object being constructed.
+pub static THIS: &'static str = "this";
+// Receiver of calls to the parent class's implementation of a method.
+pub static SUPER: &'static str = "super";
+// Inside an ensures clause, the value the function returned, and a snapshot
+// of an expression's value on entry to the function.
+pub static RESULT: &'static str = "result";
+pub static OLD: &'static str = "old";
+static END_OF_INPUT: &'static str = "end of input";
+// Synthesised per class. The first sets up an instance's declared fields
+// before its constructor runs, the second initialises the class's static
+// fields before `main` runs.
+pub static FIELD_INIT: &'static str = "%init";
+pub static STATIC_INIT: &'static str = "%clinit";
+// Initialises the global constants, before any static initialisers run.
+pub static GLOBAL_INIT: &'static str = "%globals";
 
 #[derive(Debug)]
 pub enum ParseError {
@@ -29,9 +57,113 @@ pub enum ParseError {
     FileNotFound(String),
     BrokenLexer,
     BrokenParser,
-    LexicalError,
-    SyntaxError,
+    // The lexical and syntax errors in a module, with the AST of the source
+    // as repaired to parse past them, if it could be.
+    Diagnostics(Vec<Diagnostic>, Option<Program>),
     GeneratorError(String),
+    CompileError(Vec<CompileError>),
+    ImportError(String),
+    ProjectError(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::IO(ref msg) => write!(f, "{}", msg),
+            ParseError::FileNotFound(ref path) => write!(f, "File not found: {}", path),
+            ParseError::BrokenLexer => write!(f, "Invalid lexer"),
+            ParseError::BrokenParser => write!(f, "Invalid grammar"),
+            ParseError::Diagnostics(ref diagnostics, _) => {
+                let rendered: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+                write!(f, "{}", rendered.join("\n"))
+            }
+            ParseError::CompileError(ref errors) => {
+                let rendered: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", rendered.join("\n"))
+            }
+            ParseError::GeneratorError(ref msg) | ParseError::ImportError(ref msg)
+                | ParseError::ProjectError(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticKind {
+    // A character no lexer rule matches.
+    Lexical,
+    // A token the parser can't accept.
+    Syntax,
+}
+
+// A lexical or syntax error, and where in the source it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub line: usize,
+    pub column: usize,
+    // The offending text, which is empty at the end of the input.
+    pub lexeme: String,
+    // The terminals the parser would have accepted instead.
+    pub expected: Vec<String>,
+    pub message: String,
+    // The line of source the error is on, shown with the lexeme underlined.
+    pub source_line: String,
+}
+
+impl Diagnostic {
+    fn new(kind: DiagnosticKind, input: &str, offset: usize, lexeme: String, expected: Vec<String>,
+           message: String) -> Diagnostic {
+        let (line, column) = offset_location(input, offset);
+        let line_start = offset + 1 - column;
+        let line_end = input[offset..].find('\n').map_or(input.len(), |x| offset + x);
+        Diagnostic {
+            kind: kind,
+            line: line,
+            column: column,
+            lexeme: lexeme,
+            expected: expected,
+            message: message,
+            source_line: input[line_start..line_end].to_string(),
+        }
+    }
+}
+
+// Error at line 2, column 13: unexpected ')', expected one of ...
+//     let x = );
+//             ^
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let before = &self.source_line[..self.column - 1];
+        // Tabs are kept, so that the carets line up however they're shown.
+        let indent: String = before.chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let width = self.lexeme.lines().next().map_or(0, |l| l.chars().count()).max(1);
+        write!(f, "Error at line {}, column {}: {}\n{}\n{}{}",
+               self.line, self.column, self.message, self.source_line, indent, "^".repeat(width))
+    }
+}
+
+// A mistake in a program which parsed, found while resolving its names or
+// generating its code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    // The offsets of the start and end of the offending source.
+    pub span: (usize, usize),
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new(input: &str, span: Span, message: String) -> CompileError {
+        let (line, column) = offset_location(input, span.0);
+        CompileError { span: span, line: line, column: column, message: message }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error at line {}, column {}: {}", self.line, self.column, self.message)
+    }
 }
 
 pub fn read_file(path: &Path) -> Result<String, ParseError> {
@@ -46,41 +178,377 @@ pub fn read_file(path: &Path) -> Result<String, ParseError> {
     }
 }
 
+// Settings which change the bytecode generated for a program.
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    // Leave out assert statements entirely, as for a release build.
+    pub strip_asserts: bool,
+    // Leave out the requires and ensures clauses of functions.
+    pub strip_contracts: bool,
+    // Directories to look for imported modules in, after the directory of
+    // the module importing them.
+    pub search_path: Vec<PathBuf>,
+    // The class and method the program starts in, if not `global.main`.
+    pub entry: Option<(String, String)>,
+}
+
+impl CompileOptions {
+    // Options for a release build, with asserts and contracts removed.
+    pub fn release() -> CompileOptions {
+        CompileOptions { strip_asserts: true, strip_contracts: true, ..CompileOptions::default() }
+    }
+}
+
+// Compiles a program with the grammar built into the binary. Programs given
+// as a string can only import modules on the search path.
+pub fn parse_source(source: &str) -> Result<Bytecode, ParseError> {
+    parse_source_with_options(source, &CompileOptions::default())
+}
+
+pub fn parse_source_with_options(source: &str, options: &CompileOptions) -> Result<Bytecode, ParseError> {
+    module::load_source(source.to_string(), Grammar::Builtin, options)
+}
+
+// Compiles the program whose main module is `source_path`, along with the
+// modules it imports, with the grammar built into the binary.
+pub fn parse_source_file(source_path: &Path, options: &CompileOptions) -> Result<Bytecode, ParseError> {
+    module::load_program(source_path, Grammar::Builtin, options)
+}
+
+// The functions below read the grammar from `lex_path` and `yacc_path`
+// instead, so that changes to it can be tried without rebuilding. They build
+// a new Parser each time; to compile several programs, build a Parser once
+// and use its methods.
 pub fn parse_file(source_path: &Path, lex_path: &Path, yacc_path: &Path) -> Result<Bytecode,ParseError> {
-    let input = read_file(source_path)?;
-    parse_input(input, lex_path, yacc_path)
+    parse_file_with_options(source_path, lex_path, yacc_path, &CompileOptions::default())
+}
+
+pub fn parse_file_with_options(source_path: &Path, lex_path: &Path, yacc_path: &Path,
+                               options: &CompileOptions) -> Result<Bytecode,ParseError> {
+    Parser::new(lex_path, yacc_path)?.parse_file(source_path, options)
 }
 
 pub fn parse_input(source: String, lex_path: &Path, yacc_path: &Path) -> Result<Bytecode, ParseError> {
-    let lexs = read_file(lex_path)?;
-    let mut lexer_def = build_lex::<u16>(&lexs)
-        .map_err(|_| ParseError::BrokenLexer)?;
-    let grms = read_file(yacc_path)?;
-    let grm = yacc_grm(YaccKind::Original, &grms)
-        .map_err(|_| ParseError::BrokenParser)?;
+    parse_input_with_options(source, lex_path, yacc_path, &CompileOptions::default())
+}
+
+pub fn parse_input_with_options(source: String, lex_path: &Path, yacc_path: &Path,
+                                options: &CompileOptions) -> Result<Bytecode, ParseError> {
+    Parser::new(lex_path, yacc_path)?.parse_input(source, options)
+}
+
+// The lexer and parser for grammar/, built into the binary by build.rs.
+lrlex_mod!(lexer_l);
+lrpar_mod!(grammar_y);
+include!(concat!(env!("OUT_DIR"), "/symbol_names.rs"));
+
+// Where the lexer and parser used to compile a program come from.
+#[derive(Clone, Copy)]
+pub enum Grammar<'a> {
+    // The grammar built into the binary.
+    Builtin,
+    // A grammar read from files at runtime.
+    Parser(&'a Parser),
+}
+
+// The compiler dispatches on the names of the grammar's symbols.
+pub trait SymbolNames {
+    fn nonterm_name(&self, idx: NTIdx) -> &str;
+    fn term_name(&self, idx: TIdx) -> Option<&str>;
+}
+
+impl SymbolNames for YaccGrammar {
+    fn nonterm_name(&self, idx: NTIdx) -> &str {
+        YaccGrammar::nonterm_name(self, idx)
+    }
+
+    fn term_name(&self, idx: TIdx) -> Option<&str> {
+        YaccGrammar::term_name(self, idx)
+    }
+}
+
+// The generated parser doesn't carry the grammar, so build.rs writes out its
+// symbol names alongside it.
+struct BuiltinSymbols;
+
+impl SymbolNames for BuiltinSymbols {
+    fn nonterm_name(&self, idx: NTIdx) -> &str {
+        NONTERM_NAMES[usize::from(idx)]
+    }
+
+    fn term_name(&self, idx: TIdx) -> Option<&str> {
+        TERM_NAMES[usize::from(idx)]
+    }
+}
+
+// Parses a module, failing with every lexical and syntax error found in it.
+pub fn parse_tree<'a>(source: &str, grammar: Grammar<'a>)
+                      -> Result<(&'a dyn SymbolNames, Node<u16>), ParseError> {
+    let (names, (tree, diagnostics)) = match grammar {
+        Grammar::Builtin => {
+            let names: &'a dyn SymbolNames = &BuiltinSymbols;
+            (names, parse_recovering(source, &lexer_l::lexerdef(), |l| grammar_y::parse(l), names))
+        }
+        Grammar::Parser(parser) => (&parser.grm as &dyn SymbolNames, parser.parse_tree(source))
+    };
+    match tree {
+        Some(pt) if diagnostics.is_empty() => Ok((names, pt)),
+        _ => Err(ParseError::Diagnostics(diagnostics, tree.map(|pt| ast::build(&pt, names, source))))
+    }
+}
+
+// Lexes and parses `source`, recovering from errors so that all of them are
+// reported at once. Characters no lexer rule matches are skipped, and the
+// parser repairs the token stream wherever it can't continue. The tree is
+// only missing when no repair could be found.
+fn parse_recovering<F>(source: &str, lexer_def: &LexerDef<u16>, parse: F, names: &dyn SymbolNames)
+                       -> (Option<Node<u16>>, Vec<Diagnostic>)
+    where F: ::std::ops::Fn(&[Lexeme<u16>]) -> Result<Node<u16>, (Option<Node<u16>>, Vec<parser::ParseError<u16>>)>
+{
+    let mut lexemes = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut offset = 0;
+    loop {
+        let rest = &source[offset..];
+        let (chunk, end) = match lexer_def.lexer(rest).lexemes() {
+            Ok(chunk) => (chunk, None),
+            // Everything before the unmatched character lexes as it would
+            // have done had the character not been there.
+            Err(e) => (lexer_def.lexer(&rest[..e.idx]).lexemes().unwrap_or_default(), Some(e.idx))
+        };
+        lexemes.extend(chunk.iter().map(|l| Lexeme::new(l.tok_id(), offset + l.start(), l.len())));
+        match end {
+            Some(end) => {
+                diagnostics.push(lexical_error(source, offset + end));
+                offset += end + rest[end..].chars().next().map_or(1, |c| c.len_utf8());
+            }
+            None => break
+        }
+    }
+    let tree = match parse(&lexemes) {
+        Ok(pt) => Some(pt),
+        Err((pt, errors)) => {
+            diagnostics.extend(errors.iter().map(|e| syntax_error(source, e, names)));
+            pt
+        }
+    };
+    diagnostics.sort_by_key(|d| (d.line, d.column));
+    (tree, diagnostics)
+}
+
+// A lexer and parser built from a lexer and a grammar file, which compiles
+// any number of programs without being rebuilt.
+pub struct Parser {
+    lexer_def: LexerDef<u16>,
+    grm: YaccGrammar,
+    sgraph: StateGraph,
+    stable: StateTable,
+}
+
+impl Parser {
+    pub fn new(lex_path: &Path, yacc_path: &Path) -> Result<Parser, ParseError> {
+        Parser::build(lex_path, yacc_path, None)
+    }
+
+    // Generating the state tables is the slow part of building a parser, so
+    // they are saved in `cache_dir`, under a hash of the grammar files, and
+    // loaded from there for as long as the grammar doesn't change.
+    pub fn with_cache(lex_path: &Path, yacc_path: &Path, cache_dir: &Path) -> Result<Parser, ParseError> {
+        Parser::build(lex_path, yacc_path, Some(cache_dir))
+    }
+
+    fn build(lex_path: &Path, yacc_path: &Path, cache_dir: Option<&Path>) -> Result<Parser, ParseError> {
+        let lexs = read_file(lex_path)?;
+        let mut lexer_def = build_lex::<u16>(&lexs)
+            .map_err(|_| ParseError::BrokenLexer)?;
+        let grms = read_file(yacc_path)?;
+        let grm = yacc_grm(YaccKind::Original, &grms)
+            .map_err(|_| ParseError::BrokenParser)?;
+
+        // Sync up the IDs of terminals in the lexer and parser.
+        let rule_ids = grm.terms_map()
+             .iter()
+             .map(|(&n, &i)| (n, u16::try_from(usize::from(i)).unwrap()))
+             .collect();
+        lexer_def.set_rule_ids(&rule_ids);
+
+        let cache_path = cache_dir.map(|dir| dir.join(format!("{:016x}.tables", grammar_hash(&lexs, &grms))));
+        let (sgraph, stable) = match cache_path.as_ref().and_then(|p| load_tables(p)) {
+            Some(tables) => tables,
+            None => {
+                let tables = from_yacc(&grm, Minimiser::Pager)
+                    .map_err(|_| ParseError::BrokenParser)?;
+                // The cache only saves time, so a failure to write it is
+                // ignored, leaving the tables to be generated again next time.
+                if let Some(ref p) = cache_path {
+                    store_tables(p, &tables);
+                }
+                tables
+            }
+        };
+        Ok(Parser { lexer_def: lexer_def, grm: grm, sgraph: sgraph, stable: stable })
+    }
+
+    // The parse tree of `source`, with any errors found in it. When there are
+    // errors the tree, if any, is of the repaired source.
+    pub fn parse_tree(&self, source: &str) -> (Option<Node<u16>>, Vec<Diagnostic>) {
+        parse_recovering(source, &self.lexer_def,
+                         |l| parser::parse::<u16>(&self.grm, &self.sgraph, &self.stable, l), &self.grm)
+    }
+
+    // Compiles the program whose main module is `source_path`, along with the
+    // modules it imports.
+    pub fn parse_file(&self, source_path: &Path, options: &CompileOptions) -> Result<Bytecode, ParseError> {
+        module::load_program(source_path, Grammar::Parser(self), options)
+    }
+
+    // Programs given as a string can only import modules on the search path.
+    pub fn parse_input(&self, source: String, options: &CompileOptions) -> Result<Bytecode, ParseError> {
+        module::load_source(source, Grammar::Parser(self), options)
+    }
+}
+
+// The lexer stops at the first character no rule matches.
+fn lexical_error(source: &str, offset: usize) -> Diagnostic {
+    let lexeme: String = source[offset..].chars().take(1).collect();
+    let msg = format!("unexpected character '{}'", lexeme);
+    Diagnostic::new(DiagnosticKind::Lexical, source, offset, lexeme, Vec::new(), msg)
+}
+
+fn syntax_error(source: &str, error: &parser::ParseError<u16>, names: &dyn SymbolNames) -> Diagnostic {
+    let lexeme = error.lexeme();
+    let text = source[lexeme.start()..lexeme.start() + lexeme.len()].to_string();
+    let mut expected: Vec<String> = error.expected().iter()
+        .map(|&t| names.term_name(t).unwrap_or(END_OF_INPUT).to_string())
+        .collect();
+    expected.sort();
+    expected.dedup();
+    let found = if text.is_empty() { END_OF_INPUT.to_string() } else { format!("'{}'", text) };
+    let msg = match expected.len() {
+        0 => format!("unexpected {}", found),
+        1 => format!("unexpected {}, expected {}", found, expected[0]),
+        _ => format!("unexpected {}, expected one of {}", found, expected.join(", "))
+    };
+    Diagnostic::new(DiagnosticKind::Syntax, source, lexeme.start(), text, expected, msg)
+}
+
+// Part of the key cached tables are saved under. Change it whenever lrtable
+// or bincode change how the tables are serialized, so that tables saved in
+// the old format are regenerated rather than misread.
+static TABLES_FORMAT: &'static str = "lrtable StateGraph and StateTable, bincode 1";
+
+// The tables are keyed by FNV-1a, which unlike DefaultHasher gives the same
+// hash in every build and on every platform.
+fn grammar_hash(lexs: &str, grms: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in [TABLES_FORMAT, env!("CARGO_PKG_VERSION"), lexs, grms].iter() {
+        // Each part is prefixed with its length, so that moving text from one
+        // to the next changes the hash.
+        for &byte in format!("{}:", part.len()).as_bytes().iter().chain(part.as_bytes()) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+// A missing or unreadable cache file is treated as a cache miss.
+fn load_tables(path: &Path) -> Option<(StateGraph, StateTable)> {
+    let mut bytes = Vec::new();
+    File::open(path).ok()?.read_to_end(&mut bytes).ok()?;
+    bincode::deserialize(&bytes).ok()
+}
+
+// The tables are written to a file of their own, then renamed into place,
+// so that a parser being built at the same time never loads half a file.
+fn store_tables(path: &Path, tables: &(StateGraph, StateTable)) {
+    if let Ok(bytes) = bincode::serialize(tables) {
+        let tmp_path = path.with_extension(format!("tmp{}", process::id()));
+        let stored = fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| File::create(&tmp_path))
+            .and_then(|mut f| f.write_all(&bytes))
+            .and_then(|_| fs::rename(&tmp_path, path));
+        if stored.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+    }
+}
 
-    // Sync up the IDs of terminals in the lexer and parser.
-    let rule_ids = grm.terms_map()
-         .iter()
-         .map(|(&n, &i)| (n, u16::try_from(usize::from(i)).unwrap()))
-         .collect();
-    lexer_def.set_rule_ids(&rule_ids);
+// Type checks and compiles a module on its own. The names it imports are
+// left as they are written, to be qualified when it is linked with the
+// modules they come from.
+pub fn compile(prog: &Program, source: &str, options: &CompileOptions,
+               imports: &Imports) -> Result<Bytecode, ParseError> {
+    // Each pass reports its own errors, so code is generated even for a
+    // program which doesn't resolve or type check, and they're all reported
+    // together.
+    let mut errors = resolve::resolve(prog, source, imports);
+    let mut types = typeck::check(prog, source, imports);
+    errors.append(&mut types.errors);
+    match gen_bytecode(prog, source, options, &types, imports) {
+        Ok(bytecode) if errors.is_empty() => return Ok(bytecode),
+        Ok(_) => (),
+        Err(e) => errors.extend(e)
+    }
+    // The passes check different things, so no error is reported twice.
+    // Errors at the same place are ordered by message, so that the order
+    // doesn't depend on which pass found them.
+    errors.sort_by(|a, b| (a.span, &a.message).cmp(&(b.span, &b.message)));
+    Err(ParseError::CompileError(errors))
+}
 
-    let lexer = lexer_def.lexer(&source);
-    let lexemes = lexer.lexemes().map_err(|_| ParseError::LexicalError)?;
-    let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager)
-        .map_err(|_| ParseError::BrokenParser)?;
+pub struct Import {
+    pub module: String,
+    // The names imported from the module, or none when the module itself is
+    // imported.
+    pub names: Vec<String>,
+}
 
-    let pt = parser::parse::<u16>(&grm, &sgraph, &stable, &lexemes)
-        .map_err(|_| ParseError::SyntaxError)?;
+pub fn imports(prog: &Program) -> Vec<Import> {
+    prog.decls.iter()
+        .filter_map(|decl| match *decl {
+            Decl::Import(ref import) => Some(Import {
+                module: import.module.name.clone(),
+                names: import.names.iter().map(|n| n.name.clone()).collect(),
+            }),
+            _ => None
+        })
+        .collect()
+}
 
-    Ok(gen_bytecode(&pt, &grm, &source))
+// The declarations a module can use from the modules it imports, under the
+// names it uses for them. Imported classes are also known by their
+// qualified names, so that their ancestors can be found.
+#[derive(Default)]
+pub struct Imports {
+    pub classes: HashMap<String, Class>,
+    pub traits: Vec<String>,
+    pub enums: HashMap<String, Vec<(String, usize)>>,
+    // Functions imported by name, and the class they're declared in.
+    pub functions: HashMap<String, String>,
+    // The number of parameters each imported function takes, keyed by its
+    // class and name.
+    pub arities: HashMap<(String, String), usize>,
+    // The names the module uses for imported declarations, and what they're
+    // called once linked.
+    pub names: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Instr {
     PushInt(i32),
     PushStr(String),
+    PushTrait(String),
+    PushClass(String),
+    PushFunction(String, String),
+    PushNone,
+    BuildTuple(usize),
+    RaiseAssertion(usize, usize),
+    Index,
+    Unpack(usize),
+    BuildVariant(String, String, usize),
+    MatchVariant(String, String, usize),
+    MatchTuple(usize),
     Pop,
     Add,
     Sub,
@@ -89,19 +557,34 @@ pub enum Instr {
     Lt,
     Gt,
     Eqeq,
+    // Compares the values for a literal pattern, where values of different
+    // types are unequal rather than an error.
+    MatchEq,
     Raise,
+    RaiseError(String),
     LoadVar(usize),
     StoreVar(usize),
     LoadGlobal(String),
     StoreGlobal(String),
-    NewObject,
+    LoadStatic(String, String),
+    StoreStatic(String, String),
+    NewObject(String),
     LoadField(String),
     StoreField(String),
     Swap,
     Dup,
     Call(String, String),
+    CallUnchecked(String, String),
+    CallMethod(String, usize),
+    CallBuiltin(String, usize),
+    CallValue(usize),
+    Bind(String, String),
+    GetIter,
+    IterNext,
+    Yield,
     JumpIfTrue(usize),
     JumpIfFalse(usize),
+    JumpIfExhausted(usize),
     Jump(usize),
     Ret,
     Exit,
@@ -111,6 +594,14 @@ pub enum Instr {
 pub struct Fn {
     locals: Vec<String>,
     num_params: usize,
+    generator: bool,
+    static_: bool,
+    // The annotated type of each parameter, and of the result, which the VM
+    // checks at runtime. Results the type checker has proven are not
+    // checked again.
+    param_types: Vec<Option<String>>,
+    return_type: Option<String>,
+    return_proven: bool,
 }
 
 impl Fn {
@@ -118,27 +609,121 @@ impl Fn {
         Fn {
             num_params: 0,
             locals: Vec::new(),
+            generator: false,
+            static_: false,
+            param_types: Vec::new(),
+            return_type: None,
+            return_proven: false,
         }
     }
 
+    pub fn param_name(&self, i: usize) -> &str {
+        &self.locals[i]
+    }
+
+    // The annotated type of the `i`th parameter, if it has one.
+    pub fn param_type(&self, i: usize) -> Option<&str> {
+        self.param_types.get(i).and_then(|t| t.as_ref()).map(|t| t.as_ref())
+    }
+
+    // The type the result must have, if it has to be checked when the
+    // function returns.
+    pub fn checked_return_type(&self) -> Option<&str> {
+        match self.return_type {
+            Some(ref t) if !self.return_proven => Some(t),
+            _ => None
+        }
+    }
+
+    // Static methods don't take the receiver as their first parameter.
+    pub fn is_static(&self) -> bool {
+        self.static_
+    }
+
     pub fn params_len(&self) -> usize {
         self.num_params
     }
 
+    // A function containing `yield` returns a generator when called, rather
+    // than running its body.
+    pub fn is_generator(&self) -> bool {
+        self.generator
+    }
+
     pub fn locals_len(&self) -> usize {
         self.locals.len()
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Class {
+    pub parent: Option<String>,
+    // Declared instance fields, which every instance is given before its
+    // constructor runs.
+    pub fields: Vec<String>,
+    pub static_fields: Vec<String>,
+    pub methods: Vec<String>,
+    // Members which can only be accessed by code in this class.
+    pub private: Vec<String>,
+    // Traits the class declares that it implements.
+    pub traits: Vec<String>,
+}
+
+impl Class {
+    fn new(parent: Option<String>) -> Class {
+        Class {
+            parent: parent,
+            fields: Vec::new(),
+            static_fields: Vec::new(),
+            methods: Vec::new(),
+            private: Vec::new(),
+            traits: Vec::new(),
+        }
+    }
+
+    pub fn is_private(&self, member: &str) -> bool {
+        self.private.iter().any(|x| x == member)
+    }
+}
+
+// Walks up from `class` through its ancestors, returning the first class for
+// which `pred` holds.
+fn find_in_ancestors<F>(classes: &HashMap<String, Class>, class: &str, pred: F) -> Option<String>
+    where F: ::std::ops::Fn(&str, &Class) -> bool
+{
+    let mut cur = class.to_string();
+    // Bounded by the number of classes, in case of an inheritance cycle.
+    for _ in 0..classes.len() {
+        let cls = classes.get(&cur)?;
+        if pred(&cur, cls) {
+            return Some(cur)
+        }
+        cur = cls.parent.clone()?;
+    }
+    None
+}
+
 // Conversion from the CompilerContext struct, removes the helper fields
 // which are used for building up the symbol table and bytecode. These
 // aren't needed anymore and as they are references which require a
 // lifetime, their removal makes working with the struct easier.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Bytecode {
     pub bytecode: Vec<Instr>,
     pub symbols: HashMap<(String, String), Fn>,
     pub labels: HashMap<(String, String), usize>,
+    pub classes: HashMap<String, Class>,
+    // Classes with static fields, in declaration order, which is the order
+    // their static initialisers run in.
+    pub static_init_order: Vec<String>,
+    pub traits: Vec<String>,
+    pub enums: HashMap<String, Vec<(String, usize)>>,
+    // The global classes of imported modules, in the order their constants
+    // are initialised.
+    pub imported_globals: Vec<String>,
+    // Where the program starts, if not `global.main`.
+    pub entry: Option<(String, String)>,
+    pub warnings: Vec<String>,
 }
 
 impl Bytecode {
@@ -146,31 +731,170 @@ impl Bytecode {
         Bytecode {
             bytecode: ctx.bytecode,
             symbols: ctx.symbols,
-            labels: ctx.labels
+            labels: ctx.labels,
+            classes: ctx.classes,
+            static_init_order: ctx.static_init_order,
+            traits: ctx.traits,
+            enums: ctx.enums,
+            imported_globals: Vec::new(),
+            entry: None,
+            warnings: ctx.warnings,
         }
     }
+
+    // Appends a separately compiled module. The classes, traits and enums it
+    // declares are renamed according to `names`, as are the names it
+    // imported, and its global constants are prefixed with `prefix`. Code
+    // and declarations it was given by its imports are left out, as they're
+    // already linked in.
+    pub fn link(&mut self, module: Bytecode, names: &HashMap<String, String>, prefix: &str,
+                imports: &Imports) {
+        let rename = |name: &str| names.get(name).cloned().unwrap_or_else(|| name.to_string());
+        let offset = self.bytecode.len();
+        for mut instr in module.bytecode {
+            match instr {
+                Instr::PushTrait(ref mut c) | Instr::PushClass(ref mut c) | Instr::NewObject(ref mut c)
+                    | Instr::PushFunction(ref mut c, _) | Instr::BuildVariant(ref mut c, _, _)
+                    | Instr::MatchVariant(ref mut c, _, _) | Instr::LoadStatic(ref mut c, _)
+                    | Instr::StoreStatic(ref mut c, _) | Instr::Call(ref mut c, _)
+                    | Instr::CallUnchecked(ref mut c, _) | Instr::Bind(ref mut c, _) => *c = rename(c),
+                Instr::LoadGlobal(ref mut g) | Instr::StoreGlobal(ref mut g) if !prefix.is_empty() => {
+                    *g = format!("{}.{}", prefix, g)
+                }
+                Instr::JumpIfTrue(ref mut i) | Instr::JumpIfFalse(ref mut i) | Instr::JumpIfExhausted(ref mut i)
+                    | Instr::Jump(ref mut i) => *i += offset,
+                _ => ()
+            }
+            self.bytecode.push(instr);
+        }
+        for ((class_name, fn_name), mut fn_meta) in module.symbols {
+            for t in fn_meta.param_types.iter_mut().chain(Some(&mut fn_meta.return_type)) {
+                if let Some(ref mut t) = *t {
+                    *t = rename(t);
+                }
+            }
+            let key = (rename(&class_name), fn_name);
+            self.labels.insert(key.clone(), module.labels[&(class_name, key.1.clone())] + offset);
+            self.symbols.insert(key, fn_meta);
+        }
+        for (class_name, mut class) in module.classes {
+            if imports.classes.contains_key(&class_name) {
+                continue
+            }
+            class.parent = class.parent.map(|p| rename(&p));
+            class.traits = class.traits.iter().map(|t| rename(t)).collect();
+            self.classes.insert(rename(&class_name), class);
+        }
+        self.traits.extend(module.traits.iter().filter(|t| !imports.traits.contains(t)).map(|t| rename(t)));
+        for (enum_name, variants) in module.enums {
+            if !imports.enums.contains_key(&enum_name) {
+                self.enums.insert(rename(&enum_name), variants);
+            }
+        }
+        self.static_init_order.extend(module.static_init_order.iter().map(|c| rename(c)));
+        self.imported_globals.extend(module.imported_globals);
+        if !prefix.is_empty() {
+            self.imported_globals.push(rename("global"));
+        }
+        self.warnings.extend(module.warnings);
+    }
+
+    // Resolves `class.name` to the function implementing it, which may be
+    // inherited from one of the class's ancestors.
+    pub fn lookup_method(&self, class: &str, name: &str) -> Option<(String, String)> {
+        let key = (class.to_string(), name.to_string());
+        if self.symbols.contains_key(&key) {
+            return Some(key)
+        }
+        find_in_ancestors(&self.classes, class, |c, _| {
+            self.symbols.contains_key(&(c.to_string(), name.to_string()))
+        }).map(|c| (c, name.to_string()))
+    }
+
+    // Finds the class declaring the field `class.name`, which may be one of
+    // the class's ancestors. Fields which are only ever assigned, rather
+    // than declared, have no owner.
+    pub fn field_owner(&self, class: &str, name: &str) -> Option<String> {
+        find_in_ancestors(&self.classes, class, |_, c| {
+            c.fields.iter().chain(c.static_fields.iter()).any(|f| f == name)
+        })
+    }
+
+    pub fn is_private(&self, class: &str, member: &str) -> bool {
+        self.classes.get(class).map_or(false, |c| c.is_private(member))
+    }
+
+    // Returns the class's parent, its parent's parent, and so on.
+    pub fn ancestors(&self, class: &str) -> Vec<String> {
+        let mut ancestors = Vec::new();
+        let mut cur = self.classes.get(class).and_then(|c| c.parent.clone());
+        while let Some(x) = cur {
+            // Stop at an inheritance cycle.
+            if ancestors.contains(&x) || x == class {
+                break
+            }
+            cur = self.classes.get(&x).and_then(|c| c.parent.clone());
+            ancestors.push(x);
+        }
+        ancestors
+    }
+
+    // A class implements the traits it declares, and those of its ancestors.
+    pub fn implements(&self, class: &str, trait_name: &str) -> bool {
+        find_in_ancestors(&self.classes, class, |_, c| c.traits.iter().any(|t| t == trait_name)).is_some()
+    }
 }
 
 struct CompilerContext<'pt> {
     symbols: HashMap<(String, String), Fn>,
     bytecode: Vec<Instr>,
     labels: HashMap<(String, String), usize>,
+    warnings: Vec<String>,
+    errors: Vec<CompileError>,
+    classes: HashMap<String, Class>,
+    static_init_order: Vec<String>,
+    traits: Vec<String>,
+    // Constants whose initialiser is a literal map to the instruction which
+    // pushes it, so that it can be inlined. Others map to None, and are
+    // stored as a local, static field or global respectively.
+    local_consts: HashMap<String, Option<Instr>>,
+    class_consts: HashMap<(String, String), Option<Instr>>,
+    global_consts: HashMap<String, Option<Instr>>,
+    // Each enum's variants, in declaration order, with their payload sizes.
+    enums: HashMap<String, Vec<(String, usize)>>,
+    // Whether the expression being generated is part of an ensures clause.
+    in_ensures: bool,
+    // Functions imported from other modules, and the class they belong to.
+    imported_fns: HashMap<String, String>,
 
     // Fields for convenience when building up the Bytecode struct
-    grm:        &'pt YaccGrammar,
     input:      &'pt str,
+    options:    &'pt CompileOptions,
+    types:      &'pt TypeInfo,
     cur_cls:    String,
     cur_fn:     String,
 }
 
 impl<'pt> CompilerContext<'pt> {
-    fn new(grm: &'pt YaccGrammar, input: &'pt str) -> CompilerContext<'pt> {
+    fn new(input: &'pt str, options: &'pt CompileOptions, types: &'pt TypeInfo) -> CompilerContext<'pt> {
         CompilerContext {
             symbols: HashMap::new(),
             bytecode: Vec::new(),
             labels: HashMap::new(),
-            grm:     grm,
+            warnings: Vec::new(),
+            errors: Vec::new(),
+            classes: HashMap::new(),
+            static_init_order: Vec::new(),
+            traits: Vec::new(),
+            local_consts: HashMap::new(),
+            class_consts: HashMap::new(),
+            global_consts: HashMap::new(),
+            enums: HashMap::new(),
+            in_ensures: false,
+            imported_fns: HashMap::new(),
             input:   input,
+            options: options,
+            types:   types,
             cur_cls: "global".to_string(),
             cur_fn:  "global".to_string(),
         }
@@ -184,39 +908,128 @@ impl<'pt> CompilerContext<'pt> {
         match *jump_instr {
             Instr::JumpIfTrue(ref mut _i) => *_i = patch_value,
             Instr::JumpIfFalse(ref mut _i) => *_i = patch_value,
+            Instr::JumpIfExhausted(ref mut _i) => *_i = patch_value,
+            Instr::Jump(ref mut _i) => *_i = patch_value,
             _ => panic!("Unknown jump instruction")
         }
     }
 
     // Makes a note of the current class, useful for generating metadata about
     // functions in the symbol table.
-    fn register_class(&mut self, class: &Node<u16>) {
-        match *class {
-            Node::Term { .. } => {
-                let class_name = self.get_value(class);
-                self.cur_cls   = class_name.clone();
+    fn register_class(&mut self, class: &ClassDef) {
+        self.cur_cls = class.name.name.clone();
+    }
+
+    // Adds the class and its members to the class table. This happens before
+    // any code is generated, so that static fields and inherited methods can
+    // be resolved regardless of declaration order.
+    fn register_class_decl(&mut self, class_def: &ClassDef) {
+        let class_name = class_def.name.name.clone();
+        let mut class = Class::new(class_def.parent.as_ref().map(|p| p.name.clone()));
+        class.traits = class_def.traits.iter().map(|t| t.name.clone()).collect();
+        for member in class_def.members.iter() {
+            let name = member.name().name.clone();
+            if member.is_private {
+                class.private.push(name.clone());
+            }
+            match member.kind {
+                MemberKind::Method(_) => class.methods.push(name),
+                MemberKind::Const(ref decl) => {
+                    let value = const_literal(&decl.value);
+                    if value.is_none() {
+                        class.static_fields.push(name.clone());
+                    }
+                    self.class_consts.insert((class_name.clone(), name), value);
+                }
+                MemberKind::Field(_) if member.is_static => class.static_fields.push(name),
+                MemberKind::Field(_) => class.fields.push(name),
             }
-            _ => panic!("Can only register a class on a terminal node")
         }
+        self.classes.insert(class_name, class);
     }
 
-    fn register_function(&mut self, func: &Node<u16>) -> (String, String) {
-        match *func {
-            Node::Term { .. } => {
-                let func_name = self.get_value(func);
-                self.cur_fn = func_name.clone();
-                let fn_entry_point = self.bytecode.len();
-                self.labels.insert((self.cur_cls.to_string(), func_name.to_string()), fn_entry_point);
-                self.symbols.insert((self.cur_cls.to_string(), func_name.to_string()), Fn::new());
-                return (self.cur_cls.to_string(), func_name)
-            }
-            _ => panic!("Can only register a func on a terminal node")
+    fn register_global_const(&mut self, decl: &ConstDecl) {
+        let name = decl.name.name.clone();
+        if self.global_consts.contains_key(&name) {
+            self.error(decl.span, &format!("const {} is already defined", name));
         }
+        let value = const_literal(&decl.value);
+        self.global_consts.insert(name, value);
+    }
+
+    // Returns the instruction which loads the constant `name`, if `name`
+    // refers to a constant in the current scope.
+    fn load_const(&self, name: &str) -> Option<Instr> {
+        if let Some(value) = self.local_consts.get(name) {
+            return Some(value.clone().unwrap_or_else(|| Instr::LoadVar(self.local_offset(name).unwrap())))
+        }
+        if self.local_offset(name).is_some() {
+            return None
+        }
+        self.load_class_const(&self.cur_cls, name).or_else(|| {
+            self.global_consts.get(name).map(|value| {
+                value.clone().unwrap_or_else(|| Instr::LoadGlobal(name.to_string()))
+            })
+        })
+    }
+
+    // Returns the instruction which loads the constant `cls.name`, which may
+    // be declared by one of `cls`'s ancestors.
+    fn load_class_const(&self, cls: &str, name: &str) -> Option<Instr> {
+        let owner = find_in_ancestors(&self.classes, cls, |c, _| {
+            self.class_consts.contains_key(&(c.to_string(), name.to_string()))
+        })?;
+        let value = self.class_consts[&(owner.clone(), name.to_string())].clone();
+        Some(value.unwrap_or_else(|| Instr::LoadStatic(owner, name.to_string())))
+    }
+
+    fn register_trait(&mut self, trait_def: &TraitDef) {
+        self.traits.push(trait_def.name.name.clone());
+    }
+
+    // Finds the class declaring the static field `cls.name`, which may be one
+    // of `cls`'s ancestors.
+    fn resolve_static(&mut self, span: Span, cls: &str, name: &str) -> Option<String> {
+        let owner = find_in_ancestors(&self.classes, cls, |_, c| c.static_fields.iter().any(|f| f == name));
+        if owner.is_none() {
+            self.error(span, &format!("Class {} has no static field {}", cls, name));
+        }
+        owner
+    }
+
+    // Finds the class implementing the method `cls.name`, which may be one of
+    // `cls`'s ancestors.
+    fn method_owner(&self, cls: &str, name: &str) -> Option<String> {
+        find_in_ancestors(&self.classes, cls, |_, c| c.methods.iter().any(|m| m == name))
+    }
+
+    // Private members can only be accessed from the class declaring them.
+    fn check_access(&mut self, span: Span, owner: &str, member: &str) {
+        let private = self.classes.get(owner).map_or(false, |c| c.is_private(member));
+        if private && owner != self.cur_cls {
+            self.error(span, &format!("AccessError: {}.{} is private", owner, member));
+        }
+    }
+
+    fn register_function(&mut self, func: &Ident) -> (String, String) {
+        self.register_function_name(func.name.clone())
+    }
+
+    fn register_function_name(&mut self, func_name: String) -> (String, String) {
+        self.cur_fn = func_name.clone();
+        self.local_consts.clear();
+        let fn_entry_point = self.bytecode.len();
+        self.labels.insert((self.cur_cls.to_string(), func_name.to_string()), fn_entry_point);
+        self.symbols.insert((self.cur_cls.to_string(), func_name.to_string()), Fn::new());
+        (self.cur_cls.to_string(), func_name)
     }
 
     // Adds the parameter name to the param vector of the current cls + func.
-    fn register_parameter(&mut self, param: &Node<u16>) -> usize {
-        let param_name = self.get_value(param);
+    fn register_parameter(&mut self, param: &Param) -> usize {
+        self.register_parameter_name(param.name.name.clone())
+    }
+
+    fn register_parameter_name(&mut self, param_name: String) -> usize {
         let ref key = (self.cur_cls.to_string(), self.cur_fn.to_string());
         let ref mut fn_meta = self.symbols.get_mut(key).unwrap();
         fn_meta.num_params += 1;
@@ -224,15 +1037,34 @@ impl<'pt> CompilerContext<'pt> {
         fn_meta.locals.len() - 1
     }
 
-    fn get_var_offset(&self, var: &Node<u16>) -> usize {
-        let ref var_name = self.get_value(var);
+    // Outside a function nothing is local.
+    fn local_offset(&self, var_name: &str) -> Option<usize> {
         let ref key = (self.cur_cls.to_string(), self.cur_fn.to_string());
-        let ref locals = self.symbols.get(key).unwrap().locals;
-        locals.iter().position(|x| x == var_name).unwrap()
+        let position = self.symbols.get(key).and_then(|f| f.locals.iter().position(|x| x == var_name));
+        match position {
+            // The object is always a constructor's first parameter, whatever
+            // it was called.
+            None if var_name == THIS && self.cur_fn == CONSTRUCTOR => Some(0),
+            x => x
+        }
     }
 
-    fn register_local(&mut self, var: &Node<u16>) -> usize {
-        let var_name = self.get_value(var);
+    fn register_local(&mut self, var: &Ident) -> usize {
+        let var_name = var.name.clone();
+        let key = (self.cur_cls.to_string(), self.cur_fn.to_string());
+        if !self.symbols.contains_key(&key) {
+            self.error(var.span, &format!("{} is declared outside a function", var_name));
+            return 0
+        }
+        // Locals are reassigned by declaring them again, which a local
+        // constant can't be. Class and global constants are just shadowed.
+        if self.local_consts.contains_key(&var_name) {
+            self.error(var.span, &format!("cannot assign to const {}", var_name));
+        }
+        self.register_local_name(var_name)
+    }
+
+    fn register_local_name(&mut self, var_name: String) -> usize {
         let ref key = (self.cur_cls.to_string(), self.cur_fn.to_string());
         let ref mut locals = self.symbols.get_mut(key).unwrap().locals;
         match locals.iter().position(|x| x == &var_name) {
@@ -244,281 +1076,986 @@ impl<'pt> CompilerContext<'pt> {
         }
     }
 
+    fn register_enum(&mut self, enum_def: &EnumDef) {
+        let variants = enum_def.variants.iter()
+            .map(|v| (v.name.name.clone(), v.params.len()))
+            .collect();
+        self.enums.insert(enum_def.name.name.clone(), variants);
+    }
+
+    // Returns the payload size of `enum_name.variant_name`.
+    fn variant_arity(&mut self, span: Span, enum_name: &str, variant_name: &str) -> Option<usize> {
+        let arity = self.enums.get(enum_name).unwrap().iter()
+            .find(|v| v.0 == variant_name)
+            .map(|v| v.1);
+        if arity.is_none() {
+            self.error(span, &format!("Enum {} has no variant {}", enum_name, variant_name));
+        }
+        arity
+    }
+
+    fn warn(&mut self, span: Span, msg: &str) {
+        let (line, col) = self.location(span);
+        self.warnings.push(format!("Warning at line {}, column {}: {}", line, col, msg));
+    }
+
+    // Errors don't stop code generation, so that they're all reported at
+    // once, but the code generated is thrown away.
+    fn error(&mut self, span: Span, msg: &str) {
+        let error = CompileError::new(self.input, span, msg.to_string());
+        self.errors.push(error);
+    }
+
+    fn location(&self, span: Span) -> (usize, usize) {
+        offset_location(self.input, span.0)
+    }
+
+    // The source code `span` covers.
+    fn source_text(&self, span: Span) -> &'pt str {
+        &self.input[span.0..span.1]
+    }
+
+    // Calls whose arguments the type checker proved to have their declared
+    // types skip the checks on entry to the function.
+    fn call_instr(&self, call: Span, class_name: String, fn_name: String) -> Instr {
+        if self.types.safe.contains(&call) {
+            Instr::CallUnchecked(class_name, fn_name)
+        }
+        else {
+            Instr::Call(class_name, fn_name)
+        }
+    }
+
+    // Records the annotated types of the current function's parameters and
+    // result.
+    fn set_types(&mut self, param_types: Vec<Option<String>>, return_type: Option<String>,
+                 return_proven: bool) {
+        let ref key = (self.cur_cls.to_string(), self.cur_fn.to_string());
+        let fn_meta = self.symbols.get_mut(key).unwrap();
+        fn_meta.param_types = param_types;
+        fn_meta.return_type = return_type;
+        fn_meta.return_proven = return_proven;
+    }
+
+    fn mark_generator(&mut self) {
+        let ref key = (self.cur_cls.to_string(), self.cur_fn.to_string());
+        self.symbols.get_mut(key).unwrap().generator = true;
+    }
+
+    fn mark_static(&mut self) {
+        let ref key = (self.cur_cls.to_string(), self.cur_fn.to_string());
+        self.symbols.get_mut(key).unwrap().static_ = true;
+    }
+
     fn gen_bc(&mut self , instr: Instr) -> usize {
         self.bytecode.push(instr);
         self.bytecode.len() - 1
     }
+}
 
-    fn get_value(&self, node: &Node<u16>) -> String {
-        match *node {
-            Node::Term { lexeme } => self.input[lexeme.start()..lexeme.start() + lexeme.len()]
-                                        .to_string(),
-            _ => panic!("Cannot determine name of non-terminal node")
-        }
+// The 1-based line and column where `node` starts in `input`.
+pub fn offset_location(input: &str, offset: usize) -> (usize, usize) {
+    let before = &input[..offset];
+    let line = before.matches('\n').count() + 1;
+    let col = match before.rfind('\n') {
+        Some(x) => offset - x,
+        None => offset + 1
+    };
+    (line, col)
+}
+
+// How a function is named in error messages.
+pub fn qualified_name(class_name: &str, fn_name: &str) -> String {
+    if class_name == "global" { fn_name.to_string() }
+    else { format!("{}.{}", class_name, fn_name) }
+}
+
+// Offset of the first character of `node` in the source.
+pub fn node_start(node: &Node<u16>) -> Option<usize> {
+    match *node {
+        Node::Term { lexeme } => Some(lexeme.start()),
+        Node::Nonterm { ref nodes, .. } => nodes.iter().filter_map(node_start).next()
     }
+}
 
-    fn get_name(&self, node: &Node<u16>) -> String {
-        match *node {
-            Node::Nonterm { nonterm_idx, .. } => {
-                self.grm.nonterm_name(nonterm_idx).to_string()
-            }
-            Node::Term { lexeme } => {
-                let token_id: usize = lexeme.tok_id().try_into().ok().unwrap();
-                self.grm.term_name(TIdx::from(token_id)).unwrap().to_string()
+// Offset just past the last character of `node` in the source.
+pub fn node_end(node: &Node<u16>) -> Option<usize> {
+    match *node {
+        Node::Term { lexeme } => Some(lexeme.start() + lexeme.len()),
+        Node::Nonterm { ref nodes, .. } => nodes.iter().rev().filter_map(node_end).next()
+    }
+}
+
+// Returns the instruction which pushes the literal, or why it can't be
+// compiled.
+fn literal_instr(lit: &Literal) -> Result<Instr, String> {
+    match *lit {
+        Literal::Int(ref digits) => digits.parse::<i32>().map(Instr::PushInt)
+            .map_err(|_| format!("integer literal {} is too large", digits)),
+        Literal::Str(ref contents) => Ok(Instr::PushStr(contents.clone())),
+        Literal::Bool(_) => Err("boolean literals are not supported yet".to_string())
+    }
+}
+
+// Returns the instruction pushing the value of a constant's initialiser, if
+// it's a literal and so can be inlined. Literals which can't be compiled are
+// reported when the initialiser is generated.
+fn const_literal(exp: &Expr) -> Option<Instr> {
+    match exp.kind {
+        ExprKind::Literal(ref lit) => literal_instr(lit).ok(),
+        _ => None
+    }
+}
+
+// Returns the name of an annotated type, or None for Any, which matches
+// everything.
+fn annotated_type(type_name: &Option<Ident>) -> Option<String> {
+    type_name.as_ref().map(|t| t.name.clone()).filter(|t| t != "Any")
+}
+
+fn gen_bytecode(prog: &Program, input: &str, options: &CompileOptions, types: &TypeInfo,
+                imports: &Imports) -> Result<Bytecode, Vec<CompileError>> {
+    fn gen_class(class: &ClassDef, ctx: &mut CompilerContext) {
+        ctx.register_class(class);
+        if let Some(ref parent) = class.parent {
+            if !ctx.classes.contains_key(&parent.name) {
+                ctx.error(parent.span, &format!("Unknown parent class {}", parent.name));
             }
         }
+        gen_class_body(class, ctx);
     }
-}
 
-fn gen_bytecode(parse_tree: &Node<u16>, grm: &YaccGrammar, input: &str) -> Bytecode {
-    // class_def : "CLASS" "IDENTIFIER" "LPAREN" parent_class_opt "RPAREN" "LBRACE" class_body "RBRACE";
-    // parent_class_opt :
-    //                  | "IDENTIFIER"
-    //                  ;
-    fn gen_class(node: &Node<u16>, ctx: &mut CompilerContext) {
-       if let &Node::Nonterm { ref nodes, .. } = node {
-            match ctx.get_name(node).as_ref(){
-                "class_def" => {
-                    ctx.register_class(&nodes[1]);
-                    gen_block(&nodes[5], ctx);
-                },
-                "prog" => {
-                    for child in nodes {
-                        gen_class(child, ctx)
+    fn gen_class_body(class: &ClassDef, ctx: &mut CompilerContext) {
+        let mut fields = Vec::new();
+        let mut static_fields = Vec::new();
+        let mut decorated = Vec::new();
+        for member in class.members.iter() {
+            match member.kind {
+                MemberKind::Method(ref func) => {
+                    gen_func_def(func, ctx);
+                    if member.is_static {
+                        ctx.mark_static();
+                    }
+                    if !member.decorators.is_empty() {
+                        decorated.push((ctx.cur_fn.clone(), &member.decorators[..]));
                     }
                 }
-                _ => panic!("Unknown class def")
+                _ if !member.decorators.is_empty() => {
+                    ctx.error(member.decorators[0].span, "Decorators can only be applied to methods")
+                }
+                // Literal constants are inlined, the others are static
+                // fields.
+                MemberKind::Const(ref decl) => {
+                    if const_literal(&decl.value).is_none() {
+                        static_fields.push((&decl.name, &decl.value));
+                    }
+                }
+                MemberKind::Field(ref field) if member.is_static => {
+                    static_fields.push((&field.name, &field.value))
+                }
+                MemberKind::Field(ref field) => fields.push((&field.name, &field.value)),
             }
         }
+        gen_field_init(&fields, ctx);
+        let has_constructor = find_in_ancestors(&ctx.classes, &ctx.cur_cls, |_, c| {
+            c.methods.iter().any(|m| m == CONSTRUCTOR)
+        }).is_some();
+        if !has_constructor {
+            gen_default_constructor(ctx);
+        }
+        if !static_fields.is_empty() || !decorated.is_empty() {
+            gen_static_init(&static_fields, &decorated, ctx);
+        }
     }
 
-    // block_statements : statement
-    //                  | block_statements "SEMI" statement
-    //                  ;
-    fn gen_block(node: &Node<u16>, ctx: &mut CompilerContext) {
-        if let &Node::Nonterm{ ref nodes, .. } = node {
-            for child in nodes {
-                match ctx.get_name(child).as_ref(){
-                    "statement" => gen_stmt(child, ctx),
-                    _ => gen_block(child, ctx)
-                }
+    // Generates the class's FIELD_INIT method, which runs the initialisers of
+    // the class's declared fields, after those of its parent, on the
+    // instance passed to it.
+    fn gen_field_init(fields: &[(&Ident, &Expr)], ctx: &mut CompilerContext) {
+        ctx.register_function_name(FIELD_INIT.to_string());
+        let this = ctx.register_parameter_name("this".to_string());
+        // An unknown parent class has already been reported.
+        let parent = ctx.classes.get(&ctx.cur_cls).and_then(|c| c.parent.clone())
+            .filter(|p| ctx.classes.contains_key(p));
+        if let Some(parent) = parent {
+            ctx.gen_bc(Instr::LoadVar(this));
+            ctx.gen_bc(Instr::Call(parent, FIELD_INIT.to_string()));
+            ctx.gen_bc(Instr::Pop);
+        }
+        for &(name, value) in fields.iter() {
+            gen_exp(value, ctx);
+            ctx.gen_bc(Instr::LoadVar(this));
+            ctx.gen_bc(Instr::StoreField(name.name.clone()));
+        }
+        ctx.gen_bc(Instr::Ret);
+    }
+
+    // Classes which neither declare nor inherit a constructor are given one
+    // which takes no arguments and does nothing.
+    fn gen_default_constructor(ctx: &mut CompilerContext) {
+        ctx.register_function_name(CONSTRUCTOR.to_string());
+        ctx.register_parameter_name(THIS.to_string());
+        ctx.gen_bc(Instr::Ret);
+    }
+
+    // Generates the class's STATIC_INIT function, which the VM runs once
+    // before `main`. It initialises the static fields, then rebinds each
+    // decorated method to the result of calling its decorators on it. With
+    // several decorators, the one nearest the method is called first.
+    fn gen_static_init(fields: &[(&Ident, &Expr)], decorated: &[(String, &[Expr])],
+                       ctx: &mut CompilerContext) {
+        ctx.register_function_name(STATIC_INIT.to_string());
+        let cls_name = ctx.cur_cls.clone();
+        for &(name, value) in fields.iter() {
+            gen_exp(value, ctx);
+            ctx.gen_bc(Instr::StoreStatic(cls_name.clone(), name.name.clone()));
+        }
+        for &(ref fn_name, decorators) in decorated.iter() {
+            for decorator in decorators.iter() {
+                gen_exp(decorator, ctx);
+            }
+            ctx.gen_bc(Instr::PushFunction(cls_name.clone(), fn_name.to_string()));
+            for _ in decorators.iter() {
+                ctx.gen_bc(Instr::CallValue(1));
             }
+            ctx.gen_bc(Instr::Bind(cls_name.clone(), fn_name.to_string()));
+        }
+        ctx.gen_bc(Instr::Ret);
+        let cls_name = ctx.cur_cls.clone();
+        ctx.static_init_order.push(cls_name);
+    }
+
+    fn gen_block(block: &[Stmt], ctx: &mut CompilerContext) {
+        for stmt in block.iter() {
+            gen_stmt(stmt, ctx);
         }
     }
 
-    // statement : expression
-    //           | if_statement
-    //           | let_statement
-    //           | for_statement
-    //           | try_except
-    //           | raise
-    //           ;
-    fn gen_stmt(node: &Node<u16>, ctx: &mut CompilerContext) {
-        if let &Node::Nonterm{ ref nodes, .. } = node {
-            match ctx.get_name(&nodes[0]).as_ref(){
-                "expression"    => gen_exp(&nodes[0], ctx),
-                "if_statement"  => gen_if(&nodes[0], ctx),
-                "let_statement" => gen_let(&nodes[0], ctx),
-                "func_def"      => gen_func_def(&nodes[0], ctx),
-                "for_statement" => gen_for(&nodes[0], ctx),
-                "raise"         => gen_raise(&nodes[0], ctx),
-                _ => panic!("unknown nonterminal node")
+    fn gen_stmt(stmt: &Stmt, ctx: &mut CompilerContext) {
+        match stmt.kind {
+            StmtKind::Expr(ref exp) => {
+                gen_exp(exp, ctx);
+                // The value sent into a generator isn't needed when the
+                // yield is a statement on its own. Without this, a yield
+                // in a loop would grow the generator's stack on each
+                // resume.
+                if let ExprKind::Yield(_) = exp.kind {
+                    ctx.gen_bc(Instr::Pop);
+                }
+            }
+            StmtKind::If { ref cond, ref body } => gen_if(cond, body, ctx),
+            StmtKind::Assert { ref cond, ref msg } => gen_assert(stmt.span, cond, msg, ctx),
+            StmtKind::Let { ref target, ref value, .. } => {
+                gen_exp(value, ctx);
+                gen_store(target, ctx);
             }
+            StmtKind::Const(ref decl) => gen_local_const(decl, ctx),
+            StmtKind::CompoundAssign { ref name, ref value } => gen_compound_assignment(name, value, ctx),
+            StmtKind::FuncDef(ref func) => gen_func_def(func, ctx),
+            StmtKind::For { ref init, ref cond, ref step, ref body } => gen_for(init, cond, step, body, ctx),
+            StmtKind::ForIn { ref target, ref iterable, ref body } => gen_for_in(target, iterable, body, ctx),
+            StmtKind::Match { ref scrutinee, ref arms } => gen_match(stmt.span, scrutinee, arms, ctx),
+            StmtKind::TryExcept { .. } => ctx.error(stmt.span, "try is not supported yet"),
+            StmtKind::Raise => { ctx.gen_bc(Instr::Raise); }
         }
     }
 
-    // expression : variable
-    //            | binary_expression
-    //            | method_invocation
-    //            | field_access
-    //            | class_instance_creation
-    //            | literal
-    //            ;
-    fn gen_exp(node: &Node<u16>, ctx: &mut CompilerContext) {
-        if let &Node::Nonterm{ ref nodes, .. } = node {
-            let exp_type = &nodes[0];
-            let name = ctx.get_name(exp_type);
-            if let &Node::Nonterm{ ref nodes, .. } = exp_type {
-                match name.as_ref() {
-                    "variable" => {
-                        let var_offset = ctx.get_var_offset(&nodes[0]);
-                        ctx.gen_bc(Instr::LoadVar(var_offset));
+    fn gen_exp(exp: &Expr, ctx: &mut CompilerContext) {
+        match exp.kind {
+            ExprKind::Var(ref var) => {
+                let var_name = var.name.clone();
+                if let Some(instr) = ctx.load_const(&var_name) {
+                    ctx.gen_bc(instr);
+                    return
+                }
+                if ctx.local_offset(&var_name).is_none() && ctx.traits.contains(&var_name) {
+                    ctx.gen_bc(Instr::PushTrait(var_name));
+                    return
+                }
+                if ctx.local_offset(&var_name).is_none() && ctx.classes.contains_key(&var_name) {
+                    ctx.gen_bc(Instr::PushClass(var_name));
+                    return
+                }
+                // Methods of the current class, then functions of the
+                // global class, then imported functions, can be referred
+                // to by name.
+                if ctx.local_offset(&var_name).is_none() {
+                    let cur_cls = ctx.cur_cls.clone();
+                    let owner = ctx.method_owner(&cur_cls, &var_name)
+                        .or_else(|| ctx.method_owner("global", &var_name))
+                        .or_else(|| ctx.imported_fns.get(&var_name).cloned());
+                    if let Some(owner) = owner {
+                        ctx.gen_bc(Instr::PushFunction(owner, var_name));
+                        return
                     }
-                    "binary_expression" => {
-                        gen_exp(&nodes[0], ctx);
-                        gen_exp(&nodes[2], ctx);
-                        let bin_op = &nodes[1];
-                        if let &Node::Nonterm{ref nodes, .. } = bin_op {
-                            let operator = &nodes[0];
-                            match ctx.get_name(operator).as_ref() {
-                                "PLUS"  => ctx.gen_bc(Instr::Add),
-                                "MINUS" => ctx.gen_bc(Instr::Sub),
-                                "LTEQ"  => ctx.gen_bc(Instr::Lteq),
-                                "GTEQ"  => ctx.gen_bc(Instr::Gteq),
-                                "LT"    => ctx.gen_bc(Instr::Lt),
-                                "GT"    => ctx.gen_bc(Instr::Gt),
-                                "EQEQ"  => ctx.gen_bc(Instr::Eqeq),
-                                _       => panic!("Unknown operator")
-                            };
+                }
+                if let Some(var_offset) = ctx.local_offset(&var.name) {
+                    ctx.gen_bc(Instr::LoadVar(var_offset));
+                }
+            }
+            ExprKind::Binary { ref lhs, op, ref rhs } => {
+                gen_exp(lhs, ctx);
+                gen_exp(rhs, ctx);
+                ctx.gen_bc(match op {
+                    BinOp::Add  => Instr::Add,
+                    BinOp::Sub  => Instr::Sub,
+                    BinOp::Lteq => Instr::Lteq,
+                    BinOp::Gteq => Instr::Gteq,
+                    BinOp::Lt   => Instr::Lt,
+                    BinOp::Gt   => Instr::Gt,
+                    BinOp::Eqeq => Instr::Eqeq,
+                });
+            }
+            ExprKind::MethodCall { ref receiver, ref method, ref args } => {
+                let obj_name = receiver.name.clone();
+                let method_name = method.name.clone();
+                if ctx.enums.contains_key(&obj_name) {
+                    let num_args = gen_args(args, ctx);
+                    let arity = ctx.variant_arity(method.span, &obj_name, &method_name);
+                    if arity.map_or(false, |a| a != num_args) {
+                        let msg = format!("{}.{} takes {} values, got {}",
+                                          obj_name, method_name, arity.unwrap(), num_args);
+                        ctx.error(exp.span, &msg);
+                    }
+                    ctx.gen_bc(Instr::BuildVariant(obj_name, method_name, num_args));
+                    return
+                }
+                if method_name == CONSTRUCTOR && obj_name != SUPER {
+                    let msg = format!("{} can only be called through new or {}.{}",
+                                      CONSTRUCTOR, SUPER, CONSTRUCTOR);
+                    ctx.error(method.span, &msg);
+                    return
+                }
+                if obj_name == SUPER && ctx.local_offset(&obj_name).is_none() {
+                    let parent = match ctx.classes.get(&ctx.cur_cls).and_then(|c| c.parent.clone()) {
+                        Some(x) => x,
+                        None => {
+                            let msg = format!("{} used in class {}, which has no parent",
+                                              SUPER, ctx.cur_cls);
+                            ctx.error(receiver.span, &msg);
+                            return
                         }
+                    };
+                    if let Some(owner) = ctx.method_owner(&parent, &method_name) {
+                        ctx.check_access(method.span, &owner, &method_name);
                     }
-                    "method_invocation" => {
-                        gen_args(&nodes[4], ctx);
-                        let obj_name = ctx.get_value(&nodes[0]);
-                        let method_name = ctx.get_value(&nodes[2]);
-                        ctx.gen_bc(Instr::Call(obj_name, method_name));
-                    },
-                    "method_invocation_same_class" => {
-                        gen_args(&nodes[2], ctx);
-                        let obj_name = ctx.cur_cls.clone();
-                        let method_name = ctx.get_value(&nodes[0]);
-                        ctx.gen_bc(Instr::Call(obj_name, method_name));
-                    },
-                    "field_access" => {
-                        let obj_alias = ctx.get_var_offset(&nodes[0]);
-                        let field_name = ctx.get_value(&nodes[2]);
-                        ctx.gen_bc(Instr::LoadVar(obj_alias));
-                        ctx.gen_bc(Instr::LoadField(field_name));
-                    },
-                    "field_set" => {
-                        gen_exp(&nodes[4], ctx);
-                        let obj_alias = ctx.get_var_offset(&nodes[0]);
-                        let field_name = ctx.get_value(&nodes[2]);
+                    ctx.gen_bc(Instr::LoadVar(0));
+                    gen_args(args, ctx);
+                    ctx.gen_bc(Instr::Call(parent, method_name));
+                    return
+                }
+                // A local receiver is an instance, so the method is looked
+                // up on its class at runtime. Otherwise the receiver names a
+                // class.
+                match ctx.local_offset(&obj_name) {
+                    Some(obj_alias) => {
                         ctx.gen_bc(Instr::LoadVar(obj_alias));
-                        ctx.gen_bc(Instr::StoreField(field_name));
-                    },
-                    "class_instance_creation" => {
-                        let cls_name = ctx.get_value(&nodes[1]);
-                        ctx.gen_bc(Instr::NewObject);
-                        ctx.gen_bc(Instr::Dup);
-                        gen_args(&nodes[3], ctx);
-                        ctx.gen_bc(Instr::Call(cls_name, CONSTRUCTOR.to_string()));
-                        ctx.gen_bc(Instr::Pop); // remove returned NoneType, leaving obj instance
-                    },
-                    "literal" => {
-                        let lit_type =  ctx.get_name(&nodes[0]);
-                        let lit_value = ctx.get_value(&nodes[0]);
-                        match lit_type.as_ref(){
-                            "INT_LITERAL" => {
-                                let int = lit_value.parse::<i32>().unwrap();
-                                ctx.gen_bc(Instr::PushInt(int))
-                            }
-                            "STR_LITERAL" => {
-                                ctx.gen_bc(Instr::PushStr(lit_value))
-                            }
-                            _ => panic!("NotYetImplemented")
-                        };
+                        let num_args = gen_args(args, ctx);
+                        ctx.gen_bc(Instr::CallMethod(method_name, num_args));
+                    }
+                    None => {
+                        if let Some(owner) = ctx.method_owner(&obj_name, &method_name) {
+                            ctx.check_access(method.span, &owner, &method_name);
+                        }
+                        gen_args(args, ctx);
+                        let call = ctx.call_instr(exp.span, obj_name, method_name);
+                        ctx.gen_bc(call);
                     }
-                    _ => panic!("unknown expression")
                 }
             }
+            ExprKind::Call { ref name, ref args } => {
+                let method_name = name.name.clone();
+                if method_name == OLD && ctx.in_ensures {
+                    let offset = ctx.local_offset(&old_local(name)).unwrap();
+                    ctx.gen_bc(Instr::LoadVar(offset));
+                    return
+                }
+                // A local holding a function value.
+                if let Some(offset) = ctx.local_offset(&method_name) {
+                    ctx.gen_bc(Instr::LoadVar(offset));
+                    let num_args = gen_args(args, ctx);
+                    ctx.gen_bc(Instr::CallValue(num_args));
+                    return
+                }
+                let num_args = gen_args(args, ctx);
+                let obj_name = ctx.cur_cls.clone();
+                if method_name == CONSTRUCTOR {
+                    let msg = format!("{} can only be called through new or {}.{}",
+                                      CONSTRUCTOR, SUPER, CONSTRUCTOR);
+                    ctx.error(name.span, &msg);
+                    return
+                }
+                // Methods and imported functions hide builtins of the same
+                // name.
+                let obj_name = match ctx.method_owner(&obj_name, &method_name) {
+                    Some(owner) => {
+                        ctx.check_access(name.span, &owner, &method_name);
+                        obj_name
+                    }
+                    None => match ctx.imported_fns.get(&method_name).cloned() {
+                        Some(owner) => owner,
+                        None if is_builtin(&method_name) => {
+                            ctx.gen_bc(Instr::CallBuiltin(method_name, num_args));
+                            return
+                        }
+                        None => obj_name
+                    }
+                };
+                let call = ctx.call_instr(exp.span, obj_name, method_name);
+                ctx.gen_bc(call);
+            }
+            ExprKind::Field { ref obj, ref field } => {
+                let obj_name = obj.name.clone();
+                let field_name = field.name.clone();
+                if ctx.local_offset(&obj_name).is_none() && ctx.classes.contains_key(&obj_name) {
+                    if let Some(instr) = ctx.load_class_const(&obj_name, &field_name) {
+                        if let Instr::LoadStatic(ref owner, _) = instr {
+                            ctx.check_access(field.span, owner, &field_name);
+                        }
+                        ctx.gen_bc(instr);
+                        return
+                    }
+                    if let Some(owner) = ctx.method_owner(&obj_name, &field_name) {
+                        ctx.check_access(field.span, &owner, &field_name);
+                        ctx.gen_bc(Instr::PushFunction(owner, field_name));
+                        return
+                    }
+                    if let Some(owner) = ctx.resolve_static(field.span, &obj_name, &field_name) {
+                        ctx.check_access(field.span, &owner, &field_name);
+                        ctx.gen_bc(Instr::LoadStatic(owner, field_name));
+                    }
+                    return
+                }
+                if ctx.enums.contains_key(&obj_name) {
+                    let arity = ctx.variant_arity(field.span, &obj_name, &field_name);
+                    if arity.map_or(false, |a| a != 0) {
+                        ctx.error(exp.span, &format!("{}.{} needs a payload", obj_name, field_name));
+                    }
+                    ctx.gen_bc(Instr::BuildVariant(obj_name, field_name, 0));
+                    return
+                }
+                if let Some(obj_alias) = ctx.local_offset(&obj.name) {
+                    ctx.gen_bc(Instr::LoadVar(obj_alias));
+                    ctx.gen_bc(Instr::LoadField(field_name));
+                }
+            }
+            ExprKind::FieldSet { ref obj, ref field, ref value } => {
+                gen_exp(value, ctx);
+                let obj_name = obj.name.clone();
+                let field_name = field.name.clone();
+                if ctx.local_offset(&obj_name).is_none() && ctx.classes.contains_key(&obj_name) {
+                    if ctx.load_class_const(&obj_name, &field_name).is_some() {
+                        ctx.error(field.span, &format!("cannot assign to const {}.{}",
+                                                       obj_name, field_name));
+                        return
+                    }
+                    if let Some(owner) = ctx.resolve_static(field.span, &obj_name, &field_name) {
+                        ctx.check_access(field.span, &owner, &field_name);
+                        ctx.gen_bc(Instr::StoreStatic(owner, field_name));
+                    }
+                    return
+                }
+                if let Some(obj_alias) = ctx.local_offset(&obj.name) {
+                    ctx.gen_bc(Instr::LoadVar(obj_alias));
+                    ctx.gen_bc(Instr::StoreField(field_name));
+                }
+            }
+            ExprKind::New { ref class, ref args } => {
+                let cls_name = class.name.clone();
+                ctx.gen_bc(Instr::NewObject(cls_name.clone()));
+                ctx.gen_bc(Instr::Dup);
+                ctx.gen_bc(Instr::Call(cls_name.clone(), FIELD_INIT.to_string()));
+                ctx.gen_bc(Instr::Pop);
+                ctx.gen_bc(Instr::Dup);
+                gen_args(args, ctx);
+                let call = ctx.call_instr(exp.span, cls_name, CONSTRUCTOR.to_string());
+                ctx.gen_bc(call);
+                ctx.gen_bc(Instr::Pop); // remove returned NoneType, leaving obj instance
+            }
+            ExprKind::Tuple(ref elems) => {
+                let len = gen_args(elems, ctx);
+                ctx.gen_bc(Instr::BuildTuple(len));
+            }
+            ExprKind::Index { ref obj, ref index } => {
+                gen_exp(obj, ctx);
+                gen_exp(index, ctx);
+                ctx.gen_bc(Instr::Index);
+            }
+            ExprKind::Yield(ref value) => {
+                gen_exp(value, ctx);
+                ctx.mark_generator();
+                ctx.gen_bc(Instr::Yield);
+            }
+            ExprKind::Literal(ref lit) => gen_literal(lit, exp.span, ctx),
         }
     }
 
-    // arg_list_opt :
-    //              | arg_list
-    //              ;
+    fn gen_literal(lit: &Literal, span: Span, ctx: &mut CompilerContext) {
+        match literal_instr(lit) {
+            Ok(instr) => { ctx.gen_bc(instr); }
+            Err(msg) => ctx.error(span, &msg)
+        }
+    }
 
-    // arg_list : expression
-    //          | parameter_list "COMMA" expression
-    //          ;
-    fn gen_args(node: &Node<u16>, ctx: &mut CompilerContext) {
-        if let &Node::Nonterm { ref nodes, .. } = node {
-            for child in nodes.iter() {
-                match ctx.get_name(child).as_ref() {
-                    "arg_list" => gen_args(child, ctx),
-                    "expression" => gen_exp(child, ctx),
-                    "COMMA" => (),
-                    _ => panic!("Illegal node found in arg list")
-                }
+    // Returns the number of arguments pushed onto the stack.
+    fn gen_args(args: &[Expr], ctx: &mut CompilerContext) -> usize {
+        for arg in args.iter() {
+            gen_exp(arg, ctx);
+        }
+        args.len()
+    }
+
+    // Stores the value on top of the stack in the target's locals.
+    fn gen_store(target: &Target, ctx: &mut CompilerContext) {
+        match *target {
+            Target::Name(ref name) => {
+                let var_index = ctx.register_local(name);
+                ctx.gen_bc(Instr::StoreVar(var_index));
             }
+            Target::Tuple(ref names) => gen_destructure(names, ctx)
         }
     }
 
-    //let_statement : "LET" "IDENTIFIER" "EQ" expression;
-    fn gen_let(node: &Node<u16>, ctx: &mut CompilerContext) {
-        if let &Node::Nonterm{ ref nodes, .. } = node {
-            gen_exp(&nodes[3], ctx);
-            let var_index = ctx.register_local(&nodes[1]);
+    // Literal constants are inlined where they're used, so no code is
+    // generated for them here.
+    fn gen_local_const(decl: &ConstDecl, ctx: &mut CompilerContext) {
+        let name = decl.name.name.clone();
+        if ctx.local_consts.contains_key(&name) || ctx.local_offset(&name).is_some() {
+            ctx.error(decl.name.span, &format!("{} is already defined", name));
+        }
+        let value = const_literal(&decl.value);
+        if value.is_none() {
+            gen_exp(&decl.value, ctx);
+            let var_index = ctx.register_local_name(name.clone());
             ctx.gen_bc(Instr::StoreVar(var_index));
         }
+        ctx.local_consts.insert(name, value);
+    }
+
+    fn gen_compound_assignment(name: &Ident, value: &Expr, ctx: &mut CompilerContext) {
+        if ctx.load_const(&name.name).is_some() {
+            ctx.error(name.span, &format!("cannot assign to const {}", name.name));
+            return
+        }
+        let var_index = match ctx.local_offset(&name.name) {
+            Some(x) => x,
+            None => return
+        };
+        ctx.gen_bc(Instr::LoadVar(var_index));
+        gen_exp(value, ctx);
+        ctx.gen_bc(Instr::Add);
+        ctx.gen_bc(Instr::StoreVar(var_index));
+    }
+
+    // Unpacks the tuple on top of the stack into the listed locals. The VM
+    // raises if the tuple doesn't have exactly one element per target.
+    fn gen_destructure(targets: &[Ident], ctx: &mut CompilerContext) {
+        let offsets: Vec<usize> = targets.iter()
+            .map(|t| ctx.register_local(t))
+            .collect();
+        ctx.gen_bc(Instr::Unpack(offsets.len()));
+        // Elements are pushed in order, so the last target is on top.
+        for offset in offsets.iter().rev() {
+            ctx.gen_bc(Instr::StoreVar(*offset));
+        }
+    }
+
+    fn gen_if(cond: &Expr, body: &[Stmt], ctx: &mut CompilerContext) {
+        gen_exp(cond, ctx);
+        let pos = ctx.gen_bc(Instr::JumpIfFalse(PLACEHOLDER));
+        gen_block(body, ctx);
+        ctx.patch(pos);
+    }
+
+    fn gen_assert(span: Span, cond: &Expr, msg: &Option<Expr>, ctx: &mut CompilerContext) {
+        if ctx.options.strip_asserts {
+            return
+        }
+        gen_exp(cond, ctx);
+        let pos = ctx.gen_bc(Instr::JumpIfTrue(PLACEHOLDER));
+        match *msg {
+            Some(ref msg) => gen_exp(msg, ctx),
+            None => { ctx.gen_bc(Instr::PushStr("assertion failed".to_string())); }
+        }
+        let (line, col) = ctx.location(span);
+        ctx.gen_bc(Instr::RaiseAssertion(line, col));
+        ctx.patch(pos);
+    }
+
+    fn gen_for(init: &Stmt, cond: &Expr, step: &Stmt, body: &[Stmt], ctx: &mut CompilerContext) {
+        gen_stmt(init, ctx);
+        // Loop begins
+        let loop_entry = ctx.bytecode.len();
+        gen_exp(cond, ctx); // conditional
+        let exit_call = ctx.gen_bc(Instr::JumpIfFalse(PLACEHOLDER));
+        gen_block(body, ctx); // loop body
+        gen_stmt(step, ctx); // step
+        ctx.gen_bc(Instr::Jump(loop_entry));
+        ctx.patch(exit_call);
+    }
+
+    fn gen_for_in(target: &Target, iterable: &Expr, body: &[Stmt], ctx: &mut CompilerContext) {
+        gen_exp(iterable, ctx);
+        ctx.gen_bc(Instr::GetIter);
+        // The iterator lives in a hidden local so that nested loops over
+        // the same collection each get their own.
+        let iter_name = format!("%iter{}", ctx.bytecode.len());
+        let iter_var = ctx.register_local_name(iter_name);
+        ctx.gen_bc(Instr::StoreVar(iter_var));
+        // Loop begins
+        let loop_entry = ctx.bytecode.len();
+        ctx.gen_bc(Instr::LoadVar(iter_var));
+        ctx.gen_bc(Instr::IterNext);
+        let exit_call = ctx.gen_bc(Instr::JumpIfExhausted(PLACEHOLDER));
+        gen_store(target, ctx);
+        gen_block(body, ctx); // loop body
+        ctx.gen_bc(Instr::Jump(loop_entry));
+        ctx.patch(exit_call);
+    }
+
+    // Arms are tried in order. Each arm's pattern either binds its variables
+    // and falls through into the arm's body, or jumps to the next arm.
+    fn gen_match(span: Span, scrutinee: &Expr, arms: &[MatchArm], ctx: &mut CompilerContext) {
+        gen_exp(scrutinee, ctx);
+        let scrutinee_name = format!("%match{}", ctx.bytecode.len());
+        let scrutinee = ctx.register_local_name(scrutinee_name);
+        ctx.gen_bc(Instr::StoreVar(scrutinee));
+
+        let mut exits = Vec::new();
+        for arm in arms.iter() {
+            let mut fails = Vec::new();
+            ctx.gen_bc(Instr::LoadVar(scrutinee));
+            gen_pattern(&arm.pattern, ctx, &mut fails);
+            gen_block(&arm.body, ctx);
+            exits.push(ctx.gen_bc(Instr::Jump(PLACEHOLDER)));
+            for fail in fails {
+                ctx.patch(fail);
+            }
+        }
+        ctx.gen_bc(Instr::RaiseError("MatchError: no arm matched".to_string()));
+        for exit in exits {
+            ctx.patch(exit);
+        }
+        check_exhaustive(span, arms, ctx);
+    }
+
+    // Matches the value on top of the stack against `pattern`, consuming it.
+    // On success any variables in the pattern are bound. Otherwise control
+    // jumps to a failure label, whose position is added to `fails` for
+    // patching.
+    fn gen_pattern(pattern: &Pattern, ctx: &mut CompilerContext, fails: &mut Vec<usize>) {
+        match pattern.kind {
+            PatternKind::Wildcard => { ctx.gen_bc(Instr::Pop); }
+            PatternKind::Binding(ref name) => {
+                let var_index = ctx.register_local(name);
+                ctx.gen_bc(Instr::StoreVar(var_index));
+            }
+            PatternKind::Literal(ref lit) => {
+                gen_literal(lit, pattern.span, ctx);
+                ctx.gen_bc(Instr::MatchEq);
+                fails.push(ctx.gen_bc(Instr::JumpIfFalse(PLACEHOLDER)));
+            }
+            PatternKind::Variant { ref enum_name, ref variant, ref fields } => {
+                if !ctx.enums.contains_key(&enum_name.name) {
+                    ctx.error(enum_name.span, &format!("Unknown enum {}", enum_name.name));
+                    return
+                }
+                let arity = match ctx.variant_arity(variant.span, &enum_name.name, &variant.name) {
+                    Some(x) => x,
+                    None => return
+                };
+                if fields.len() != arity {
+                    let msg = format!("{}.{} has {} values, but the pattern has {}",
+                                      enum_name.name, variant.name, arity, fields.len());
+                    ctx.error(pattern.span, &msg);
+                }
+                ctx.gen_bc(Instr::MatchVariant(enum_name.name.clone(), variant.name.clone(), arity));
+                fails.push(ctx.gen_bc(Instr::JumpIfFalse(PLACEHOLDER)));
+                gen_subpatterns(fields, ctx, fails);
+            }
+            PatternKind::Tuple(ref elems) => {
+                ctx.gen_bc(Instr::MatchTuple(elems.len()));
+                fails.push(ctx.gen_bc(Instr::JumpIfFalse(PLACEHOLDER)));
+                gen_subpatterns(elems, ctx, fails);
+            }
+        }
     }
 
-    //raise : "RAISE";
-    fn gen_raise(node: &Node<u16>, ctx: &mut CompilerContext) {
-        if let &Node::Nonterm{..} = node {
-            ctx.gen_bc(Instr::Raise);
+    // Matches the elements left on the stack by a successful `MatchVariant` or
+    // `MatchTuple`. They're stashed in hidden locals first, so that a failing
+    // subpattern doesn't leave the rest behind on the stack.
+    fn gen_subpatterns(subpatterns: &[Pattern], ctx: &mut CompilerContext,
+                       fails: &mut Vec<usize>) {
+        let pos = ctx.bytecode.len();
+        let elems: Vec<usize> = (0..subpatterns.len())
+            .map(|i| ctx.register_local_name(format!("%pat{}_{}", pos, i)))
+            .collect();
+        for elem in elems.iter().rev() {
+            ctx.gen_bc(Instr::StoreVar(*elem));
+        }
+        for (elem, subpattern) in elems.iter().zip(subpatterns.iter()) {
+            ctx.gen_bc(Instr::LoadVar(*elem));
+            gen_pattern(subpattern, ctx, fails);
         }
     }
 
-    //if_statement : "IF" expression block;
-    fn gen_if(node: &Node<u16>, ctx: &mut CompilerContext) {
-        if let &Node::Nonterm{ ref nodes, .. } = node {
-            gen_exp(&nodes[1], ctx);
-            let pos = ctx.gen_bc(Instr::JumpIfFalse(PLACEHOLDER));
-            gen_block(&nodes[2], ctx);
-            ctx.patch(pos);
+    // Warns about a match over an enum which has no catch-all arm and doesn't
+    // cover every variant. A variant only counts as covered by an arm whose
+    // subpatterns are all plain bindings or wildcards.
+    fn check_exhaustive(span: Span, arms: &[MatchArm], ctx: &mut CompilerContext) {
+        let mut enum_name = None;
+        let mut covered = Vec::new();
+        for arm in arms.iter() {
+            match arm.pattern.kind {
+                PatternKind::Wildcard | PatternKind::Binding(_) => return,
+                PatternKind::Variant { enum_name: ref name, ref variant, ref fields } => {
+                    let irrefutable = fields.iter().all(|p| match p.kind {
+                        PatternKind::Wildcard | PatternKind::Binding(_) => true,
+                        _ => false
+                    });
+                    enum_name = Some(name.name.clone());
+                    if irrefutable {
+                        covered.push(variant.name.clone());
+                    }
+                }
+                _ => ()
+            }
+        }
+        // An unknown enum has already been reported.
+        if let Some(variants) = enum_name.as_ref().and_then(|e| ctx.enums.get(e)) {
+            let enum_name = enum_name.as_ref().unwrap();
+            let missing: Vec<String> = variants.iter()
+                .map(|v| v.0.to_string())
+                .filter(|v| !covered.contains(v))
+                .collect();
+            if !missing.is_empty() {
+                let msg = format!("non-exhaustive match over {}, missing {}",
+                                  enum_name, missing.join(", "));
+                ctx.warn(span, &msg);
+            }
         }
     }
 
-    //for_statement : "FOR" "LPAREN" statement "SEMI" expression "SEMI" statement "RPAREN" block;
-    fn gen_for(node: &Node<u16>, ctx: &mut CompilerContext) {
-        if let &Node::Nonterm{ ref nodes, .. } = node {
-            gen_stmt(&nodes[2], ctx);
-            // Loop begins
-            let loop_entry = ctx.bytecode.len();
-            gen_exp(&nodes[4], ctx); // conditional
-            let exit_call = ctx.gen_bc(Instr::JumpIfFalse(PLACEHOLDER));
-            gen_block(&nodes[8], ctx); // loop body
-            gen_stmt(&nodes[6], ctx); // step
-            ctx.gen_bc(Instr::Jump(loop_entry));
-            ctx.patch(exit_call);
+    fn gen_func_def(func: &FuncDef, ctx: &mut CompilerContext) {
+        let (cls_name, fn_name) = ctx.register_function(&func.name);
+        for param in func.params.iter() {
+            ctx.register_parameter(param);
+        }
+        let param_types = func.params.iter().map(|p| annotated_type(&p.type_name)).collect();
+        let return_type = annotated_type(&func.return_type);
+        let return_proven = ctx.types.safe.contains(&func.name.span);
+        ctx.set_types(param_types, return_type, return_proven);
+        // A constructor declared without parameters still receives the
+        // new object.
+        if fn_name == CONSTRUCTOR && ctx.symbols[&(cls_name.clone(), fn_name.clone())].params_len() == 0 {
+            ctx.register_parameter_name(THIS.to_string());
+        }
+        let (requires, ensures) = if ctx.options.strip_contracts {
+            (&[][..], &[][..])
         }
+        else {
+            (&func.requires[..], &func.ensures[..])
+        };
+        let qualified_name = qualified_name(&cls_name, &fn_name);
+        for clause in requires {
+            gen_contract_check(clause, &qualified_name, "requires", ctx);
+        }
+        if !ensures.is_empty() {
+            gen_old_snapshots(ensures, ctx);
+            // The function's result if the body leaves nothing behind.
+            ctx.gen_bc(Instr::PushNone);
+        }
+        gen_block(&func.body, ctx);
+        if !ensures.is_empty() {
+            let key = (cls_name.clone(), fn_name.clone());
+            if ctx.symbols[&key].generator {
+                ctx.error(func.name.span, "generators cannot have ensures clauses");
+            }
+            let result = ctx.register_local_name(RESULT.to_string());
+            ctx.gen_bc(Instr::StoreVar(result));
+            ctx.in_ensures = true;
+            for clause in ensures {
+                gen_contract_check(clause, &qualified_name, "ensures", ctx);
+            }
+            ctx.in_ensures = false;
+            ctx.gen_bc(Instr::LoadVar(result));
+        }
+        if (cls_name, fn_name) == ("global".to_string(), "main".to_string()) {
+            ctx.gen_bc(Instr::Exit);
+        }
+        else {
+            ctx.gen_bc(Instr::Ret);
+        }
+    }
+
+    fn gen_contract_check(clause: &Expr, fn_name: &str, kind: &str,
+                          ctx: &mut CompilerContext) {
+        gen_exp(clause, ctx);
+        let pos = ctx.gen_bc(Instr::JumpIfTrue(PLACEHOLDER));
+        let msg = format!("ContractError: {} {} {}", fn_name, kind, ctx.source_text(clause.span));
+        ctx.gen_bc(Instr::RaiseError(msg));
+        ctx.patch(pos);
     }
 
-    // func_def : "DEF" "IDENTIFIER" "LPAREN" parameter_list_opt "RPAREN" block ;
-    fn gen_func_def(node: &Node<u16>, ctx: &mut CompilerContext) {
-        if let &Node::Nonterm{ ref nodes, .. } = node {
-            let (cls_name, fn_name) = ctx.register_function(&nodes[1]);
-            gen_params(&nodes[3], ctx);
-            gen_block(&nodes[5], ctx);
-            if (cls_name, fn_name) == ("global".to_string(), "main".to_string()) {
-                ctx.gen_bc(Instr::Exit);
+    // Evaluates the argument of each `old(...)` in the ensures clauses on
+    // entry to the function, storing it in a hidden local for the clause to
+    // load later.
+    fn gen_old_snapshots(ensures: &[Expr], ctx: &mut CompilerContext) {
+        fn find_olds<'a>(exp: &'a Expr, olds: &mut Vec<&'a Expr>) {
+            if let ExprKind::Call { ref name, .. } = exp.kind {
+                if name.name == OLD {
+                    olds.push(exp);
+                    return
+                }
             }
-            else {
-                ctx.gen_bc(Instr::Ret);
+            for child in exp.children() {
+                find_olds(child, olds);
+            }
+        }
+
+        let mut olds = Vec::new();
+        for clause in ensures {
+            find_olds(clause, &mut olds);
+        }
+        for old in olds {
+            if let ExprKind::Call { ref name, ref args } = old.kind {
+                if gen_args(args, ctx) != 1 {
+                    ctx.error(old.span, &format!("{} takes exactly one expression", OLD));
+                }
+                let offset = ctx.register_local_name(old_local(name));
+                ctx.gen_bc(Instr::StoreVar(offset));
             }
         }
     }
 
-    // parameter_list : "IDENTIFIER"
-    //                | parameter_list "COMMA" "IDENTIFIER"
-    //                ;
-    fn gen_params(node: &Node<u16>, ctx: &mut CompilerContext) {
-        match *node {
-            Node::Nonterm { ref nodes, ..} => {
-                for child in nodes.iter() {
-                    gen_params(child, ctx)
+    // The hidden local holding the snapshot taken for the `old` call whose
+    // name is `name`.
+    fn old_local(name: &Ident) -> String {
+        format!("%old{}", name.span.0)
+    }
+
+    // Checks that each class has every method required by the traits it
+    // implements, with the same number of parameters. Classes which neither
+    // declare nor inherit a method get the trait's default body, if it has
+    // one. This runs once all classes have been generated, so that inherited
+    // methods can be checked regardless of declaration order.
+    fn gen_trait_impls(prog: &Program, ctx: &mut CompilerContext) {
+        let mut traits = HashMap::new();
+        for decl in prog.decls.iter() {
+            if let Decl::Trait(ref trait_def) = *decl {
+                traits.insert(trait_def.name.name.clone(), trait_def);
+            }
+        }
+        for decl in prog.decls.iter() {
+            if let Decl::Class(ref class) = *decl {
+                ctx.register_class(class);
+                for trait_name in class.traits.iter() {
+                    let trait_def = match traits.get(&trait_name.name) {
+                        Some(x) => *x,
+                        // Imported traits' methods are only checked at
+                        // runtime.
+                        None if ctx.traits.contains(&trait_name.name) => continue,
+                        None => {
+                            ctx.error(trait_name.span, &format!("Unknown trait {}", trait_name.name));
+                            continue
+                        }
+                    };
+                    for method in trait_def.methods.iter() {
+                        gen_trait_method(&trait_name.name, method, class, ctx);
+                    }
                 }
             }
-            Node::Term{..} => {
-                if ctx.get_name(node) == "IDENTIFIER" {
-                    ctx.register_parameter(node);
+        }
+    }
+
+    fn gen_trait_method(trait_name: &str, method: &TraitMethod, class: &ClassDef,
+                        ctx: &mut CompilerContext) {
+        let method_name = method.name.name.clone();
+        let num_params = method.params.len();
+        let cls_name = ctx.cur_cls.clone();
+        match ctx.method_owner(&cls_name, &method_name) {
+            Some(owner) => {
+                // Methods inherited from imported classes are only checked
+                // at runtime.
+                let declared = match ctx.symbols.get(&(owner.clone(), method_name.clone())) {
+                    Some(f) => f.params_len(),
+                    None => return
+                };
+                if declared != num_params {
+                    let msg = format!("{}.{} takes {} parameters, but {}.{} requires {}",
+                                      owner, method_name, declared, trait_name, method_name,
+                                      num_params);
+                    ctx.error(class.name.span, &msg);
+                }
+            }
+            None => match method.default {
+                Some(ref func) => {
+                    gen_func_def(func, ctx);
+                    ctx.classes.get_mut(&cls_name).unwrap().methods.push(method_name);
+                }
+                None => {
+                    let msg = format!("Class {} does not implement {}.{}",
+                                      cls_name, trait_name, method_name);
+                    ctx.error(class.name.span, &msg);
                 }
             }
         }
     }
 
-    let mut ctx = CompilerContext::new(grm, input);
-    match *parse_tree {
-        Node::Nonterm { ref nodes, .. } => {
-            for cls in nodes.iter() {
-                gen_class(cls, &mut ctx);
+    // Generates the GLOBAL_INIT function, which stores the global constants
+    // that can't be inlined. The VM runs it before anything else.
+    fn gen_global_init(prog: &Program, ctx: &mut CompilerContext) {
+        let consts: Vec<&ConstDecl> = prog.decls.iter()
+            .filter_map(|d| match *d {
+                Decl::Const(ref c) if const_literal(&c.value).is_none() => Some(c),
+                _ => None
+            })
+            .collect();
+        if consts.is_empty() {
+            return
+        }
+        ctx.cur_cls = "global".to_string();
+        ctx.register_function_name(GLOBAL_INIT.to_string());
+        for decl in consts {
+            gen_exp(&decl.value, ctx);
+            ctx.gen_bc(Instr::StoreGlobal(decl.name.name.clone()));
+        }
+        ctx.gen_bc(Instr::Ret);
+    }
+
+    // Classes, enums, traits and global constants can be used before they're
+    // declared, so they're all registered before generating any code.
+    fn register_decls(prog: &Program, ctx: &mut CompilerContext) {
+        for decl in prog.decls.iter() {
+            match *decl {
+                Decl::Class(ref class) => ctx.register_class_decl(class),
+                Decl::Enum(ref enum_def) => ctx.register_enum(enum_def),
+                Decl::Trait(ref trait_def) => ctx.register_trait(trait_def),
+                Decl::Const(ref decl) => ctx.register_global_const(decl),
+                Decl::Import(_) => ()
             }
         }
-        _ => panic!("Error")
     }
-    Bytecode::new(ctx)
+
+    let mut ctx = CompilerContext::new(input, options, types);
+    ctx.classes.extend(imports.classes.clone());
+    ctx.traits.extend(imports.traits.iter().cloned());
+    ctx.enums.extend(imports.enums.clone());
+    ctx.imported_fns = imports.functions.clone();
+    register_decls(prog, &mut ctx);
+    for decl in prog.decls.iter() {
+        if let Decl::Class(ref class) = *decl {
+            gen_class(class, &mut ctx);
+        }
+    }
+    gen_trait_impls(prog, &mut ctx);
+    gen_global_init(prog, &mut ctx);
+    if !ctx.errors.is_empty() {
+        let mut errors = ctx.errors;
+        errors.sort_by_key(|e| e.span);
+        return Err(errors)
+    }
+    Ok(Bytecode::new(ctx))
 }
 
 #[cfg(test)]
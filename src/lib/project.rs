@@ -0,0 +1,151 @@
+// A project is a directory holding a `plang.toml` manifest:
+//
+//     [project]
+//     name = "app"
+//     main = "app.plang"
+//     entry = "App.start"
+//     search_path = ["lib"]
+//
+//     [dependencies]
+//     geometry = { path = "../geometry" }
+//
+// `main` is the main module, `main.plang` if not given, and `entry` the
+// global function or static method the program starts in, `global.main` if
+// not given. Search paths are relative to the project directory. Each
+// dependency is another project on the local filesystem, whose directory and
+// search paths are searched for modules after the project's own, followed by
+// those of its dependencies in turn. Nothing is ever fetched.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+use parse::{Bytecode, CompileOptions, ParseError, parse_source_file, read_file};
+
+pub static MANIFEST: &'static str = "plang.toml";
+static DEFAULT_MAIN: &'static str = "main.plang";
+
+#[derive(Debug)]
+pub struct Project {
+    pub name: String,
+    pub root: PathBuf,
+    pub main: PathBuf,
+    pub entry: Option<(String, String)>,
+    pub search_path: Vec<PathBuf>,
+    pub dependencies: Vec<Project>,
+}
+
+impl Project {
+    pub fn load(dir: &Path) -> Result<Project, ParseError> {
+        let mut loading = Vec::new();
+        Project::load_dependency(dir, &mut loading)
+    }
+
+    // `loading` holds the projects whose dependencies are being loaded, so
+    // that a project depending on itself is reported rather than loaded
+    // forever.
+    fn load_dependency(dir: &Path, loading: &mut Vec<PathBuf>) -> Result<Project, ParseError> {
+        let root = dir.canonicalize().map_err(|_| error(dir, "no such project directory"))?;
+        let source = read_file(&root.join(MANIFEST))?;
+        let manifest: Value = source.parse()
+            .map_err(|e| error(&root, &format!("invalid manifest: {}", e)))?;
+        let project = manifest.get("project").and_then(|p| p.as_table())
+            .ok_or_else(|| error(&root, "missing [project] table"))?;
+        let name = project.get("name").and_then(|n| n.as_str())
+            .ok_or_else(|| error(&root, "missing project name"))?
+            .to_string();
+        let main = match project.get("main") {
+            Some(m) => m.as_str().ok_or_else(|| error(&root, "main must be a string"))?,
+            None => DEFAULT_MAIN
+        };
+        let entry = match project.get("entry") {
+            Some(e) => Some(entry_point(e.as_str().ok_or_else(|| error(&root, "entry must be a string"))?)),
+            None => None
+        };
+        let mut search_path = Vec::new();
+        if let Some(paths) = project.get("search_path") {
+            let paths = paths.as_array().ok_or_else(|| error(&root, "search_path must be an array"))?;
+            for p in paths {
+                let p = p.as_str().ok_or_else(|| error(&root, "search_path must hold strings"))?;
+                search_path.push(root.join(p));
+            }
+        }
+
+        loading.push(root.clone());
+        let mut dependencies = Vec::new();
+        if let Some(deps) = manifest.get("dependencies") {
+            let deps = deps.as_table().ok_or_else(|| error(&root, "[dependencies] must be a table"))?;
+            for (dep_name, dep) in deps.iter() {
+                let path = dep.get("path").and_then(|p| p.as_str())
+                    .ok_or_else(|| error(&root, &format!("dependency {} has no path", dep_name)))?;
+                let dir = root.join(path);
+                if dir.canonicalize().map(|d| loading.contains(&d)).unwrap_or(false) {
+                    return Err(error(&root, &format!("dependency cycle through {}", dep_name)))
+                }
+                let dependency = Project::load_dependency(&dir, loading)?;
+                if dependency.name != *dep_name {
+                    return Err(error(&root, &format!("dependency {} is named {}", dep_name, dependency.name)))
+                }
+                dependencies.push(dependency);
+            }
+        }
+        loading.pop();
+
+        Ok(Project {
+            name: name,
+            main: root.join(main),
+            root: root,
+            entry: entry,
+            search_path: search_path,
+            dependencies: dependencies,
+        })
+    }
+
+    // Every directory modules are looked for in, after the directory of the
+    // module importing them.
+    pub fn module_path(&self) -> Vec<PathBuf> {
+        let mut path = Vec::new();
+        let mut seen = HashSet::new();
+        self.add_module_path(&mut path, &mut seen);
+        // The main module's own directory is searched first anyway.
+        path.retain(|p| Some(p.as_path()) != self.main.parent());
+        path
+    }
+
+    fn add_module_path(&self, path: &mut Vec<PathBuf>, seen: &mut HashSet<PathBuf>) {
+        let dirs = Some(&self.root).into_iter().chain(self.search_path.iter());
+        for dir in dirs {
+            if seen.insert(dir.clone()) {
+                path.push(dir.clone());
+            }
+        }
+        for dependency in self.dependencies.iter() {
+            dependency.add_module_path(path, seen);
+        }
+    }
+}
+
+// Compiles the project in `dir`. The manifest's entry point and search paths
+// are added to `options`.
+pub fn parse_project(dir: &Path, options: &CompileOptions) -> Result<Bytecode, ParseError> {
+    let project = Project::load(dir)?;
+    let mut options = options.clone();
+    options.search_path.extend(project.module_path());
+    if project.entry.is_some() {
+        options.entry = project.entry.clone();
+    }
+    parse_source_file(&project.main, &options)
+}
+
+// `Class.method`, or just `function` for a global function.
+fn entry_point(entry: &str) -> (String, String) {
+    match entry.rfind('.') {
+        Some(i) => (entry[..i].to_string(), entry[i + 1..].to_string()),
+        None => ("global".to_string(), entry.to_string())
+    }
+}
+
+fn error(dir: &Path, msg: &str) -> ParseError {
+    ParseError::ProjectError(format!("{}: {}", dir.join(MANIFEST).display(), msg))
+}
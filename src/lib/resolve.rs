@@ -0,0 +1,550 @@
+// Name resolution, which runs on the AST before any bytecode is generated. It
+// reports undefined variables, calls to functions and classes which don't
+// exist, calls with the wrong number of arguments, and methods or parameters
+// which are declared twice. Left to the VM, a bad call either panics or pops
+// the wrong number of values off the operand stack.
+//
+// Calls on a local are dispatched on the receiver's class at runtime, so
+// only their arguments are resolved here.
+
+use std::collections::{HashMap, HashSet};
+
+use ast::{ClassDef, Decl, Expr, ExprKind, FuncDef, Ident, MemberKind, Pattern, PatternKind, Program,
+          Span, Stmt, StmtKind, Target, TraitDef};
+use interp::is_builtin;
+use parse::{qualified_name, CompileError, Imports, CONSTRUCTOR, FIELD_INIT, GLOBAL_INIT, OLD, RESULT,
+            STATIC_INIT, SUPER, THIS};
+
+pub fn resolve(prog: &Program, input: &str, imports: &Imports) -> Vec<CompileError> {
+    let mut resolver = Resolver::new(input, imports);
+    resolver.declare(prog);
+    for decl in prog.decls.iter() {
+        match *decl {
+            Decl::Class(ref class) => resolver.class(class),
+            Decl::Const(ref decl) => {
+                resolver.enter("global", GLOBAL_INIT);
+                resolver.exp(&decl.value);
+            }
+            _ => ()
+        }
+    }
+    // Trait methods' default bodies are resolved as part of each class
+    // given them.
+    let defaults = resolver.defaults.clone();
+    for (class_name, func) in defaults {
+        resolver.enter(&class_name, "");
+        resolver.func_def(func);
+    }
+    resolver.errors
+}
+
+struct Resolver<'a> {
+    input: &'a str,
+    imports: &'a Imports,
+    // Each of the module's classes, and its parent.
+    parents: HashMap<String, Option<String>>,
+    // The number of parameters each of the module's functions takes, as
+    // the VM counts them.
+    arities: HashMap<(String, String), usize>,
+    class_consts: HashSet<(String, String)>,
+    global_consts: HashSet<String>,
+    traits: HashSet<String>,
+    enums: HashSet<String>,
+    // The trait methods' default bodies each class is given.
+    defaults: Vec<(String, &'a FuncDef)>,
+    errors: Vec<CompileError>,
+    cur_cls: String,
+    cur_fn: String,
+    // The locals and constants of the current function, which are visible
+    // from where they're declared to the end of the function.
+    locals: HashSet<String>,
+    // Whether the current function has ensures clauses, in which `result`
+    // is the value it returns and so can't be a local's name.
+    has_ensures: bool,
+    in_ensures: bool,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(input: &'a str, imports: &'a Imports) -> Resolver<'a> {
+        Resolver {
+            input: input,
+            imports: imports,
+            parents: HashMap::new(),
+            arities: HashMap::new(),
+            class_consts: HashSet::new(),
+            global_consts: HashSet::new(),
+            traits: HashSet::new(),
+            enums: HashSet::new(),
+            defaults: Vec::new(),
+            errors: Vec::new(),
+            cur_cls: "global".to_string(),
+            cur_fn: String::new(),
+            locals: HashSet::new(),
+            has_ensures: false,
+            in_ensures: false,
+        }
+    }
+
+    // Everything the module declares can be used before its declaration, so
+    // it's all recorded before any names are resolved.
+    fn declare(&mut self, prog: &'a Program) {
+        let mut traits = HashMap::new();
+        for decl in prog.decls.iter() {
+            match *decl {
+                Decl::Class(ref class) => self.declare_class(class),
+                Decl::Enum(ref enum_def) => { self.enums.insert(enum_def.name.name.clone()); }
+                Decl::Trait(ref trait_def) => {
+                    self.traits.insert(trait_def.name.name.clone());
+                    traits.insert(trait_def.name.name.clone(), trait_def);
+                }
+                Decl::Const(ref decl) => { self.global_consts.insert(decl.name.name.clone()); }
+                Decl::Import(_) => ()
+            }
+        }
+        // Classes which neither declare nor inherit a trait's method are
+        // given its default body.
+        for decl in prog.decls.iter() {
+            if let Decl::Class(ref class) = *decl {
+                for trait_name in class.traits.iter() {
+                    if let Some(trait_def) = traits.get(&trait_name.name) {
+                        self.declare_defaults(&class.name.name, trait_def);
+                    }
+                }
+            }
+        }
+    }
+
+    fn declare_class(&mut self, class: &ClassDef) {
+        let class_name = class.name.name.clone();
+        self.parents.insert(class_name.clone(), class.parent.as_ref().map(|p| p.name.clone()));
+        for member in class.members.iter() {
+            match member.kind {
+                MemberKind::Method(ref func) => {
+                    // A constructor declared without parameters still
+                    // receives the new object.
+                    let mut arity = func.params.len();
+                    if func.name.name == CONSTRUCTOR && arity == 0 {
+                        arity = 1;
+                    }
+                    self.arities.insert((class_name.clone(), func.name.name.clone()), arity);
+                    self.declare_nested(&class_name, &func.body);
+                }
+                MemberKind::Const(ref decl) => {
+                    self.class_consts.insert((class_name.clone(), decl.name.name.clone()));
+                }
+                MemberKind::Field(_) => ()
+            }
+        }
+    }
+
+    // Functions defined inside a method belong to the method's class.
+    fn declare_nested(&mut self, class_name: &str, block: &[Stmt]) {
+        for stmt in block.iter() {
+            match stmt.kind {
+                StmtKind::FuncDef(ref func) => {
+                    self.arities.insert((class_name.to_string(), func.name.name.clone()), func.params.len());
+                    self.declare_nested(class_name, &func.body);
+                }
+                StmtKind::If { ref body, .. } | StmtKind::For { ref body, .. }
+                    | StmtKind::ForIn { ref body, .. } => self.declare_nested(class_name, body),
+                StmtKind::Match { ref arms, .. } => {
+                    for arm in arms.iter() {
+                        self.declare_nested(class_name, &arm.body);
+                    }
+                }
+                _ => ()
+            }
+        }
+    }
+
+    fn declare_defaults(&mut self, class_name: &str, trait_def: &'a TraitDef) {
+        for method in trait_def.methods.iter() {
+            if let Some(ref func) = method.default {
+                if self.arity(class_name, &method.name.name).is_none() {
+                    let key = (class_name.to_string(), method.name.name.clone());
+                    self.arities.insert(key, func.params.len());
+                    self.defaults.push((class_name.to_string(), func));
+                }
+            }
+        }
+    }
+
+    fn class(&mut self, class: &ClassDef) {
+        let class_name = class.name.name.clone();
+        let mut methods = HashSet::new();
+        for member in class.members.iter() {
+            // Decorators and static initialisers run in the class's static
+            // initialiser, and field initialisers in its field initialiser,
+            // which is passed the instance.
+            self.enter(&class_name, STATIC_INIT);
+            for decorator in member.decorators.iter() {
+                self.exp(decorator);
+            }
+            match member.kind {
+                MemberKind::Method(ref func) => {
+                    if !methods.insert(func.name.name.clone()) {
+                        let msg = format!("{} is already defined", qualified_name(&class_name, &func.name.name));
+                        self.error(func.name.span, &msg);
+                    }
+                    self.func_def(func);
+                }
+                MemberKind::Const(ref decl) => self.exp(&decl.value),
+                MemberKind::Field(ref field) if member.is_static => self.exp(&field.value),
+                MemberKind::Field(ref field) => {
+                    self.enter(&class_name, FIELD_INIT);
+                    self.locals.insert("this".to_string());
+                    self.exp(&field.value);
+                }
+            }
+        }
+    }
+
+    // Starts resolving the code of `cls.func`, which has no locals yet.
+    fn enter(&mut self, cls: &str, func: &str) {
+        self.cur_cls = cls.to_string();
+        self.cur_fn = func.to_string();
+        self.locals.clear();
+    }
+
+    fn func_def(&mut self, func: &FuncDef) {
+        // A nested function has its own locals, and those of the function
+        // around it are visible again after it.
+        let outer_fn = self.cur_fn.clone();
+        let outer_locals = self.locals.clone();
+        let outer_ensures = self.has_ensures;
+        self.cur_fn = func.name.name.clone();
+        self.locals.clear();
+        self.has_ensures = !func.ensures.is_empty();
+        for param in func.params.iter() {
+            if !self.add_local(&param.name) {
+                let msg = format!("{} has more than one parameter named {}",
+                                  qualified_name(&self.cur_cls, &func.name.name), param.name.name);
+                self.error(param.name.span, &msg);
+            }
+        }
+        for clause in func.requires.iter() {
+            self.exp(clause);
+        }
+        // The arguments of `old` are evaluated on entry to the function.
+        for clause in func.ensures.iter() {
+            self.old_args(clause);
+        }
+        self.block(&func.body);
+        if !func.ensures.is_empty() {
+            self.locals.insert(RESULT.to_string());
+            self.in_ensures = true;
+            for clause in func.ensures.iter() {
+                self.exp(clause);
+            }
+            self.in_ensures = false;
+        }
+        self.cur_fn = outer_fn;
+        self.locals = outer_locals;
+        self.has_ensures = outer_ensures;
+    }
+
+    // Adds a local, returning false if the function already has one of that
+    // name.
+    fn add_local(&mut self, name: &Ident) -> bool {
+        if self.has_ensures && name.name == RESULT {
+            let msg = format!("cannot declare {} in a function with ensures clauses", RESULT);
+            self.error(name.span, &msg);
+        }
+        self.locals.insert(name.name.clone())
+    }
+
+    fn old_args(&mut self, exp: &Expr) {
+        if let ExprKind::Call { ref name, ref args } = exp.kind {
+            if name.name == OLD {
+                self.exps(args);
+                return
+            }
+        }
+        for child in exp.children() {
+            self.old_args(child);
+        }
+    }
+
+    fn block(&mut self, block: &[Stmt]) {
+        for stmt in block.iter() {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt.kind {
+            StmtKind::Expr(ref exp) => self.exp(exp),
+            StmtKind::If { ref cond, ref body } => {
+                self.exp(cond);
+                self.block(body);
+            }
+            StmtKind::Assert { ref cond, ref msg } => {
+                self.exp(cond);
+                if let Some(ref msg) = *msg {
+                    self.exp(msg);
+                }
+            }
+            StmtKind::Let { ref target, ref value, .. } => {
+                self.exp(value);
+                self.bind(target);
+            }
+            StmtKind::Const(ref decl) => {
+                self.exp(&decl.value);
+                self.add_local(&decl.name);
+            }
+            StmtKind::CompoundAssign { ref name, ref value } => {
+                // Assigning to a constant is reported by the compiler.
+                if !self.is_local(&name.name) && !self.is_const(&name.name) {
+                    self.undefined(name);
+                }
+                self.exp(value);
+            }
+            StmtKind::FuncDef(ref func) => self.func_def(func),
+            StmtKind::For { ref init, ref cond, ref step, ref body } => {
+                self.stmt(init);
+                self.exp(cond);
+                self.block(body);
+                self.stmt(step);
+            }
+            StmtKind::ForIn { ref target, ref iterable, ref body } => {
+                self.exp(iterable);
+                self.bind(target);
+                self.block(body);
+            }
+            StmtKind::Match { ref scrutinee, ref arms } => {
+                self.exp(scrutinee);
+                for arm in arms.iter() {
+                    self.pattern(&arm.pattern);
+                    self.block(&arm.body);
+                }
+            }
+            // Reported by the compiler, which doesn't support them yet.
+            StmtKind::TryExcept { .. } => (),
+            StmtKind::Raise => ()
+        }
+    }
+
+    fn bind(&mut self, target: &Target) {
+        match *target {
+            Target::Name(ref name) => { self.add_local(name); }
+            Target::Tuple(ref names) => {
+                for name in names.iter() {
+                    self.add_local(name);
+                }
+            }
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern.kind {
+            PatternKind::Binding(ref name) => { self.add_local(name); }
+            PatternKind::Variant { ref fields, .. } | PatternKind::Tuple(ref fields) => {
+                for field in fields.iter() {
+                    self.pattern(field);
+                }
+            }
+            PatternKind::Wildcard | PatternKind::Literal(_) => ()
+        }
+    }
+
+    fn exps(&mut self, exps: &[Expr]) {
+        for exp in exps.iter() {
+            self.exp(exp);
+        }
+    }
+
+    fn exp(&mut self, exp: &Expr) {
+        match exp.kind {
+            ExprKind::Var(ref var) => {
+                let name = &var.name;
+                let known = self.is_local(name) || self.is_const(name) || self.is_class(name)
+                    || self.traits.contains(name) || self.imports.traits.contains(name)
+                    || self.function(name).is_some();
+                if !known {
+                    self.undefined(var);
+                }
+            }
+            ExprKind::MethodCall { ref receiver, ref method, ref args } => {
+                self.exps(args);
+                let obj_name = &receiver.name;
+                // Enum variants are checked by the compiler.
+                if self.is_enum(obj_name) {
+                    return
+                }
+                if obj_name == SUPER && !self.is_local(obj_name) {
+                    // A class without a parent is reported by the compiler.
+                    let parent = self.parents.get(&self.cur_cls).and_then(|p| p.clone());
+                    let label = format!("{}.{}", SUPER, method.name);
+                    match parent {
+                        Some(ref parent) if method.name == CONSTRUCTOR => {
+                            let arity = self.arity(parent, CONSTRUCTOR).map_or(1, |(_, arity)| arity);
+                            self.check_arity(exp.span, &label, arity, 1, args.len());
+                        }
+                        Some(ref parent) => self.call(exp.span, parent, &label, method, 1, args.len()),
+                        None => ()
+                    }
+                    return
+                }
+                // Other calls to constructors are reported by the compiler.
+                if method.name == CONSTRUCTOR {
+                    return
+                }
+                if self.is_local(obj_name) {
+                    return
+                }
+                if self.is_class(obj_name) {
+                    let label = format!("{}.{}", obj_name, method.name);
+                    self.call(exp.span, obj_name, &label, method, 0, args.len());
+                }
+                else {
+                    self.undefined(receiver);
+                }
+            }
+            ExprKind::Call { ref name, ref args } => {
+                // The argument of `old` was resolved on entry to the
+                // function.
+                if name.name == OLD && self.in_ensures {
+                    return
+                }
+                self.exps(args);
+                if self.is_local(&name.name) || name.name == CONSTRUCTOR {
+                    return
+                }
+                let cur_cls = self.cur_cls.clone();
+                let owner = match self.arity(&cur_cls, &name.name) {
+                    Some(_) => Some(cur_cls),
+                    None => self.imports.functions.get(&name.name).cloned()
+                };
+                match owner {
+                    Some(owner) => self.call(exp.span, &owner, &name.name, name, 0, args.len()),
+                    None if is_builtin(&name.name) => (),
+                    None => self.error(name.span, &format!("undefined function {}", name.name))
+                }
+            }
+            ExprKind::Field { ref obj, .. } => {
+                if !self.is_local(&obj.name) && !self.is_class(&obj.name) && !self.is_enum(&obj.name) {
+                    self.undefined(obj);
+                }
+            }
+            ExprKind::FieldSet { ref obj, ref value, .. } => {
+                self.exp(value);
+                if !self.is_local(&obj.name) && !self.is_class(&obj.name) {
+                    self.undefined(obj);
+                }
+            }
+            ExprKind::New { ref class, ref args } => {
+                self.exps(args);
+                if !self.is_class(&class.name) {
+                    self.error(class.span, &format!("Unknown class {}", class.name));
+                    return
+                }
+                // The constructor is also passed the new object. Classes
+                // which neither declare nor inherit one are given one which
+                // takes nothing else.
+                let arity = self.arity(&class.name, CONSTRUCTOR).map_or(1, |(_, arity)| arity);
+                let label = format!("{}.{}", class.name, CONSTRUCTOR);
+                self.check_arity(exp.span, &label, arity, 1, args.len());
+            }
+            ExprKind::Binary { .. } | ExprKind::Tuple(_) | ExprKind::Index { .. }
+                | ExprKind::Yield(_) | ExprKind::Literal(_) => {
+                for child in exp.children() {
+                    self.exp(child);
+                }
+            }
+        }
+    }
+
+    // Checks a call to `cls.method`, written as `label`, which is passed
+    // `num_args` values besides the `implicit` ones.
+    fn call(&mut self, span: Span, cls: &str, label: &str, method: &Ident, implicit: usize, num_args: usize) {
+        match self.arity(cls, &method.name) {
+            Some((_, arity)) => self.check_arity(span, label, arity, implicit, num_args),
+            None => self.error(method.span, &format!("Class {} has no method {}", cls, method.name))
+        }
+    }
+
+    // Parameters filled in by the call itself, like the object passed to a
+    // constructor, aren't counted in the message.
+    fn check_arity(&mut self, span: Span, label: &str, arity: usize, implicit: usize, num_args: usize) {
+        if arity != num_args + implicit {
+            let expected = arity.saturating_sub(implicit);
+            let noun = if expected == 1 { "argument" } else { "arguments" };
+            let msg = format!("{} takes {} {}, got {}", label, expected, noun, num_args);
+            self.error(span, &msg);
+        }
+    }
+
+    // Methods of the current class, then functions of the global class, then
+    // imported functions, can be referred to by name.
+    fn function(&self, name: &str) -> Option<(String, usize)> {
+        self.arity(&self.cur_cls, name)
+            .or_else(|| self.arity("global", name))
+            .or_else(|| {
+                let owner = self.imports.functions.get(name)?;
+                self.arity(owner, name)
+            })
+    }
+
+    // Finds the class implementing `cls.name`, which may be one of `cls`'s
+    // ancestors, and how many parameters it takes.
+    fn arity(&self, cls: &str, name: &str) -> Option<(String, usize)> {
+        let mut cur = cls.to_string();
+        // Bounded by the number of classes, in case of an inheritance cycle.
+        for _ in 0..self.parents.len() + self.imports.classes.len() + 1 {
+            let key = (cur.clone(), name.to_string());
+            if let Some(arity) = self.arities.get(&key).or_else(|| self.imports.arities.get(&key)) {
+                return Some((cur, *arity))
+            }
+            cur = self.parent(&cur)?;
+        }
+        None
+    }
+
+    fn parent(&self, cls: &str) -> Option<String> {
+        match self.parents.get(cls) {
+            Some(parent) => parent.clone(),
+            None => self.imports.classes.get(cls).and_then(|c| c.parent.clone())
+        }
+    }
+
+    // The object is always a constructor's first parameter, whatever it was
+    // called.
+    fn is_local(&self, name: &str) -> bool {
+        self.locals.contains(name) || (name == THIS && self.cur_fn == CONSTRUCTOR)
+    }
+
+    // Class constants may be declared by one of the current class's
+    // ancestors.
+    fn is_const(&self, name: &str) -> bool {
+        let mut cur = Some(self.cur_cls.clone());
+        for _ in 0..self.parents.len() + 1 {
+            match cur {
+                Some(cls) => {
+                    if self.class_consts.contains(&(cls.clone(), name.to_string())) {
+                        return true
+                    }
+                    cur = self.parents.get(&cls).and_then(|p| p.clone());
+                }
+                None => break
+            }
+        }
+        self.global_consts.contains(name)
+    }
+
+    fn is_class(&self, name: &str) -> bool {
+        self.parents.contains_key(name) || self.imports.classes.contains_key(name)
+    }
+
+    fn is_enum(&self, name: &str) -> bool {
+        self.enums.contains(name) || self.imports.enums.contains_key(name)
+    }
+
+    fn undefined(&mut self, var: &Ident) {
+        self.error(var.span, &format!("undefined variable {}", var.name));
+    }
+
+    fn error(&mut self, span: Span, msg: &str) {
+        let error = CompileError::new(self.input, span, msg.to_string());
+        self.errors.push(error);
+    }
+}
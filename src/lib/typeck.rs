@@ -0,0 +1,807 @@
+// A static checker for programs with type annotations. It runs on the AST
+// before any bytecode is generated. Anything without an annotation has
+// the type Any, which is compatible with every other type, so unannotated
+// code is still only checked at runtime.
+//
+// The VM checks the arguments and results of typed functions as they're
+// called and return. Where the checker proves those types, it records the
+// call or function so the compiler can leave the runtime check out. A type
+// is only proven if the value can't have come from anywhere unchecked:
+// annotating a local or field doesn't check what's stored in it, so a local
+// given a value of type Any isn't proven, and fields never are.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use ast::{BinOp, Decl, Expr, ExprKind, FuncDef, Ident, Literal, MemberKind, Param, Program, Span,
+          Stmt, StmtKind, Target};
+use parse::{CompileError, Imports};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Any,
+    Int,
+    Str,
+    Bool,
+    NoneType,
+    Tuple,
+    // An instance of a class or enum, or of a class implementing a trait.
+    Class(String),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Type::Any => write!(f, "Any"),
+            Type::Int => write!(f, "Int"),
+            Type::Str => write!(f, "Str"),
+            Type::Bool => write!(f, "Bool"),
+            Type::NoneType => write!(f, "None"),
+            Type::Tuple => write!(f, "Tuple"),
+            Type::Class(ref name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Sig {
+    params: Vec<(String, Type)>,
+    ret: Type,
+    static_: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ClassInfo {
+    parent: Option<String>,
+    traits: Vec<String>,
+    fields: HashMap<String, Type>,
+    methods: HashMap<String, Sig>,
+}
+
+#[derive(Default)]
+pub struct TypeInfo {
+    pub errors: Vec<CompileError>,
+    // The spans of calls whose arguments, and of the names of functions
+    // whose results, are known to have their declared types.
+    pub safe: HashSet<Span>,
+}
+
+struct Checker<'a> {
+    input: &'a str,
+    classes: HashMap<String, ClassInfo>,
+    traits: HashMap<String, ClassInfo>,
+    enums: Vec<String>,
+    // The declarations imported from other modules, whose members aren't
+    // checked here, though the classes they extend and the traits they
+    // implement are known.
+    imports: &'a Imports,
+    errors: Vec<CompileError>,
+    safe: HashSet<Span>,
+    cur_cls: String,
+    // The current method's first parameter, when the method never assigns
+    // to it. Its fields are checked against the class's declarations, but
+    // otherwise it's Any, as the method can be called through its class
+    // with any value.
+    receiver: Option<String>,
+    // The types of the current function's parameters and locals.
+    locals: HashMap<String, Type>,
+    // The locals whose values are known to have their types when they're
+    // used, with those types.
+    proven: HashMap<String, Type>,
+}
+
+pub fn check(prog: &Program, input: &str, imports: &Imports) -> TypeInfo {
+    let mut checker = Checker {
+        input: input,
+        classes: HashMap::new(),
+        traits: HashMap::new(),
+        enums: Vec::new(),
+        imports: imports,
+        errors: Vec::new(),
+        safe: HashSet::new(),
+        cur_cls: "global".to_string(),
+        receiver: None,
+        locals: HashMap::new(),
+        proven: HashMap::new(),
+    };
+    checker.declare(prog);
+    checker.check_prog(prog);
+    TypeInfo { errors: checker.errors, safe: checker.safe }
+}
+
+
+impl<'a> Checker<'a> {
+    // Records every class, trait and enum name first, so that annotations
+    // can refer to types declared later, then the signatures of their
+    // members.
+    fn declare(&mut self, prog: &Program) {
+        for decl in prog.decls.iter() {
+            match *decl {
+                Decl::Class(ref class) => {
+                    self.classes.insert(class.name.name.clone(), ClassInfo::default());
+                }
+                Decl::Trait(ref trait_def) => {
+                    self.traits.insert(trait_def.name.name.clone(), ClassInfo::default());
+                }
+                Decl::Enum(ref enum_def) => self.enums.push(enum_def.name.name.clone()),
+                Decl::Const(_) | Decl::Import(_) => ()
+            }
+        }
+        for decl in prog.decls.iter() {
+            match *decl {
+                Decl::Trait(ref trait_def) => {
+                    let mut info = ClassInfo::default();
+                    // A default method, or just a signature.
+                    for method in trait_def.methods.iter() {
+                        let sig = self.signature(&method.params, &method.return_type, false);
+                        info.methods.insert(method.name.name.clone(), sig);
+                    }
+                    self.traits.insert(trait_def.name.name.clone(), info);
+                }
+                Decl::Class(ref class) => {
+                    let mut info = ClassInfo {
+                        parent: class.parent.as_ref().map(|p| p.name.clone()),
+                        traits: class.traits.iter().map(|t| t.name.clone()).collect(),
+                        ..ClassInfo::default()
+                    };
+                    for member in class.members.iter() {
+                        match member.kind {
+                            MemberKind::Method(ref func) => {
+                                let sig = self.signature(&func.params, &func.return_type, member.is_static);
+                                info.methods.insert(func.name.name.clone(), sig);
+                            }
+                            MemberKind::Field(ref field) => {
+                                let field_type = match field.type_name {
+                                    Some(ref type_name) => self.resolve(type_name),
+                                    None => Type::Any
+                                };
+                                info.fields.insert(field.name.name.clone(), field_type);
+                            }
+                            MemberKind::Const(_) => ()
+                        }
+                    }
+                    self.classes.insert(class.name.name.clone(), info);
+                }
+                _ => ()
+            }
+        }
+    }
+
+    fn check_prog(&mut self, prog: &Program) {
+        for decl in prog.decls.iter() {
+            match *decl {
+                Decl::Trait(ref trait_def) => {
+                    self.cur_cls = trait_def.name.name.clone();
+                    for method in trait_def.methods.iter() {
+                        if let Some(ref func) = method.default {
+                            let sig = self.traits[&self.cur_cls].methods[&func.name.name].clone();
+                            self.check_fn(func, sig, true);
+                        }
+                    }
+                }
+                Decl::Class(ref class) => {
+                    self.cur_cls = class.name.name.clone();
+                    for member in class.members.iter() {
+                        match member.kind {
+                            MemberKind::Method(ref func) => {
+                                let sig = self.classes[&self.cur_cls].methods[&func.name.name].clone();
+                                self.check_fn(func, sig, true);
+                            }
+                            MemberKind::Field(ref field) => {
+                                self.locals.clear();
+                                let (actual, _) = self.infer(&field.value);
+                                let declared = self.classes[&self.cur_cls].fields[&field.name.name].clone();
+                                if !self.assignable(&actual, &declared) {
+                                    let msg = format!("field {}.{} is declared as {}, got {}",
+                                                      self.cur_cls, field.name.name, declared, actual);
+                                    self.error(field.name.span, msg);
+                                }
+                            }
+                            MemberKind::Const(ref decl) => {
+                                self.locals.clear();
+                                self.infer(&decl.value);
+                            }
+                        }
+                    }
+                }
+                _ => ()
+            }
+        }
+    }
+
+    fn check_fn(&mut self, func: &FuncDef, sig: Sig, is_method: bool) {
+        let outer = ::std::mem::replace(&mut self.locals, HashMap::new());
+        let outer_proven = ::std::mem::replace(&mut self.proven, HashMap::new());
+        let outer_receiver = self.receiver.take();
+        // The arguments were either checked on entry or proven at the call.
+        for &(ref name, ref param_type) in sig.params.iter() {
+            self.locals.insert(name.clone(), param_type.clone());
+            self.proven.insert(name.clone(), param_type.clone());
+        }
+        if is_method && !sig.static_ && self.cur_cls != "global" {
+            if let Some(receiver) = func.params.first().filter(|p| p.type_name.is_none()) {
+                if !assigned(&func.body).contains(&receiver.name.name) {
+                    self.receiver = Some(receiver.name.name.clone());
+                }
+            }
+        }
+        for clause in func.requires.iter() {
+            self.infer(clause);
+        }
+        let (body_type, body_proven) = self.block(&func.body);
+        // Generators return an iterator rather than the body's value.
+        if !has_yield(&func.body) {
+            if !self.assignable(&body_type, &sig.ret) {
+                let msg = format!("{} is declared to return {}, got {}",
+                                  func.name.name, sig.ret, body_type);
+                self.error(func.name.span, msg);
+            }
+            else if body_proven {
+                self.safe.insert(func.name.span);
+            }
+        }
+        self.locals.insert("result".to_string(), sig.ret.clone());
+        self.proven.insert("result".to_string(), sig.ret.clone());
+        for clause in func.ensures.iter() {
+            self.infer(clause);
+        }
+        self.locals = outer;
+        self.proven = outer_proven;
+        self.receiver = outer_receiver;
+    }
+
+    // Returns the type of the block's value, which is that of its last
+    // statement, and whether it's proven.
+    fn block(&mut self, block: &[Stmt]) -> (Type, bool) {
+        let mut block_type = (Type::Any, false);
+        for stmt in block.iter() {
+            block_type = self.stmt(stmt);
+        }
+        block_type
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> (Type, bool) {
+        match stmt.kind {
+            StmtKind::Expr(ref exp) => return self.infer(exp),
+            StmtKind::Let { ref target, ref type_name, ref value } => {
+                let (actual, proven) = self.infer(value);
+                let name = match *target {
+                    Target::Name(ref name) => name,
+                    Target::Tuple(ref names) => {
+                        self.unbind(names.iter());
+                        return (Type::Any, false)
+                    }
+                };
+                let declared = match *type_name {
+                    Some(ref type_name) => self.resolve(type_name),
+                    None => self.locals.get(&name.name).cloned().unwrap_or(Type::Any)
+                };
+                if !self.assignable(&actual, &declared) {
+                    let msg = format!("{} is declared as {}, got {}", name.name, declared, actual);
+                    self.error(name.span, msg);
+                }
+                // Nothing checks the value stored, so the local only has its
+                // declared type for certain if the value was proven.
+                if proven {
+                    self.proven.insert(name.name.clone(), declared.clone());
+                }
+                else {
+                    self.proven.remove(&name.name);
+                }
+                self.locals.insert(name.name.clone(), declared);
+            }
+            // Constants can't be reassigned, so they keep their initialiser's
+            // type.
+            StmtKind::Const(ref decl) => {
+                let (const_type, proven) = self.infer(&decl.value);
+                if proven {
+                    self.proven.insert(decl.name.name.clone(), const_type.clone());
+                }
+                self.locals.insert(decl.name.name.clone(), const_type);
+            }
+            StmtKind::CompoundAssign { ref name, ref value } => {
+                let declared = self.locals.get(&name.name).cloned().unwrap_or(Type::Any);
+                let (rhs, proven) = self.infer(value);
+                let sum = self.binary(BinOp::Add, declared.clone(), rhs, stmt.span);
+                if !self.assignable(&sum, &declared) {
+                    let msg = format!("{} is declared as {}, got {}", name.name, declared, sum);
+                    self.error(name.span, msg);
+                }
+                if !proven || !self.is_proven(&name.name) || sum != declared {
+                    self.proven.remove(&name.name);
+                }
+            }
+            StmtKind::FuncDef(ref func) => {
+                let sig = self.signature(&func.params, &func.return_type, false);
+                if let Some(info) = self.classes.get_mut(&self.cur_cls) {
+                    info.methods.insert(func.name.name.clone(), sig.clone());
+                }
+                self.check_fn(func, sig, false);
+            }
+            // A block which may not run only keeps the proofs which hold
+            // both with and without it.
+            StmtKind::If { ref cond, ref body } => {
+                self.infer(cond);
+                let before = self.proven.clone();
+                self.block(body);
+                self.meet(&before);
+            }
+            StmtKind::Assert { ref cond, ref msg } => {
+                self.infer(cond);
+                if let Some(ref msg) = *msg {
+                    self.infer(msg);
+                }
+            }
+            // A loop's body may run after itself, so anything it assigns
+            // isn't proven at the start of each iteration.
+            StmtKind::For { ref init, ref cond, ref step, ref body } => {
+                self.stmt(init);
+                self.forget(body);
+                self.forget(::std::slice::from_ref(&**step));
+                let head = self.proven.clone();
+                self.infer(cond);
+                self.block(body);
+                self.stmt(step);
+                self.meet(&head);
+            }
+            StmtKind::ForIn { ref target, ref iterable, ref body } => {
+                self.infer(iterable);
+                self.unbind(target.names().into_iter());
+                self.forget(body);
+                let head = self.proven.clone();
+                self.block(body);
+                self.meet(&head);
+            }
+            StmtKind::Match { ref scrutinee, ref arms } => {
+                self.infer(scrutinee);
+                let before = self.proven.clone();
+                let mut after: Option<HashMap<String, Type>> = None;
+                for arm in arms.iter() {
+                    self.proven = before.clone();
+                    self.unbind(arm.pattern.bindings().into_iter());
+                    self.block(&arm.body);
+                    if let Some(ref after) = after {
+                        self.meet(after);
+                    }
+                    after = Some(self.proven.clone());
+                }
+                self.proven = after.unwrap_or(before);
+            }
+            // The handler may run after any part of the body.
+            StmtKind::TryExcept { ref body, ref handler } => {
+                self.forget(body);
+                let before = self.proven.clone();
+                self.block(body);
+                let after = ::std::mem::replace(&mut self.proven, before);
+                self.block(handler);
+                self.meet(&after);
+            }
+            StmtKind::Raise => ()
+        }
+        (Type::Any, false)
+    }
+
+    // Locals bound by a tuple, loop or pattern have whatever type their value
+    // has.
+    fn unbind<'n, I: Iterator<Item = &'n Ident>>(&mut self, names: I) {
+        for name in names {
+            self.locals.insert(name.name.clone(), Type::Any);
+            self.proven.remove(&name.name);
+        }
+    }
+
+    // Drops the proofs of the locals assigned in `block`.
+    fn forget(&mut self, block: &[Stmt]) {
+        for name in assigned(block) {
+            self.proven.remove(&name);
+        }
+    }
+
+    // Keeps only the proofs which also hold in `other`.
+    fn meet(&mut self, other: &HashMap<String, Type>) {
+        self.proven.retain(|name, proven_type| other.get(name) == Some(proven_type));
+    }
+
+    fn is_proven(&self, name: &str) -> bool {
+        match (self.locals.get(name), self.proven.get(name)) {
+            (Some(local_type), Some(proven_type)) => local_type == proven_type && *local_type != Type::Any,
+            _ => false
+        }
+    }
+
+    // Returns the type of the expression, and whether its value is proven to
+    // have that type when it runs. Values are proven if they're built from
+    // literals, proven locals, or the results of typed functions called
+    // without dynamic dispatch, whose results the VM checks or the checker
+    // proved. Fields aren't, as nothing checks the values stored in them.
+    fn infer(&mut self, exp: &Expr) -> (Type, bool) {
+        match exp.kind {
+            ExprKind::Literal(Literal::Int(_)) => (Type::Int, true),
+            ExprKind::Literal(Literal::Str(_)) => (Type::Str, true),
+            ExprKind::Literal(Literal::Bool(_)) => (Type::Bool, true),
+            ExprKind::Var(ref var) => {
+                let var_type = self.locals.get(&var.name).cloned().unwrap_or(Type::Any);
+                (var_type, self.is_proven(&var.name))
+            }
+            ExprKind::Binary { ref lhs, op, ref rhs } => {
+                let (lhs, lhs_proven) = self.infer(lhs);
+                let (rhs, rhs_proven) = self.infer(rhs);
+                // A class's special method is found on the object at runtime.
+                let proven = if let Type::Class(_) = lhs { false } else { lhs_proven && rhs_proven };
+                let result = self.binary(op, lhs, rhs, exp.span);
+                let proven = proven && result != Type::Any;
+                (result, proven)
+            }
+            ExprKind::MethodCall { ref receiver, ref method, ref args } => {
+                let args = self.args(args);
+                // Overriding methods may have other types.
+                if let Some(receiver_type) = self.locals.get(&receiver.name).cloned() {
+                    return (self.method_call(&receiver_type, &method.name, &args, exp.span), false)
+                }
+                if self.enums.contains(&receiver.name) {
+                    return (Type::Class(receiver.name.clone()), true)
+                }
+                match self.method(&receiver.name, &method.name) {
+                    Some(sig) => {
+                        let label = format!("{}.{}", receiver.name, method.name);
+                        self.check_call(exp.span, &label, &sig.params, &args);
+                        let proven = sig.ret != Type::Any;
+                        (sig.ret, proven)
+                    }
+                    None => (Type::Any, false)
+                }
+            }
+            ExprKind::Call { ref name, ref args } => {
+                let args = self.args(args);
+                if name.name == "old" && args.len() == 1 {
+                    return (args[0].1.clone(), args[0].2)
+                }
+                if self.locals.contains_key(&name.name) {
+                    return (Type::Any, false)
+                }
+                let cur_cls = self.cur_cls.clone();
+                match self.method(&cur_cls, &name.name).or_else(|| self.method("global", &name.name)) {
+                    Some(sig) => {
+                        self.check_call(exp.span, &name.name, &sig.params, &args);
+                        // A trait's default method is called on the class
+                        // given it, which may override the method called.
+                        let proven = sig.ret != Type::Any && !self.traits.contains_key(&cur_cls);
+                        (sig.ret, proven)
+                    }
+                    None => match name.name.as_ref() {
+                        "implements" | "isinstance" | "hasattr" => (Type::Bool, false),
+                        _ => (Type::Any, false)
+                    }
+                }
+            }
+            ExprKind::Field { ref obj, ref field } => {
+                (self.declared_field(obj, field, exp.span).unwrap_or(Type::Any), false)
+            }
+            ExprKind::FieldSet { ref obj, ref field, ref value } => {
+                let (actual, _) = self.infer(value);
+                let declared = self.declared_field(obj, field, exp.span).unwrap_or(Type::Any);
+                if !self.assignable(&actual, &declared) {
+                    let msg = format!("field {}.{} is declared as {}, got {}",
+                                      obj.name, field.name, declared, actual);
+                    self.error(exp.span, msg);
+                }
+                (actual, false)
+            }
+            ExprKind::New { ref class, ref args } => {
+                let args = self.args(args);
+                if !self.classes.contains_key(&class.name) {
+                    return (Type::Any, false)
+                }
+                // The constructor's first parameter is the new object.
+                if let Some(sig) = self.method(&class.name, "construct") {
+                    let label = format!("{}.construct", class.name);
+                    let params = if sig.params.is_empty() { &sig.params[..] } else { &sig.params[1..] };
+                    self.check_call(exp.span, &label, params, &args);
+                }
+                (Type::Class(class.name.clone()), true)
+            }
+            ExprKind::Tuple(ref elems) => {
+                self.args(elems);
+                (Type::Tuple, true)
+            }
+            ExprKind::Index { .. } | ExprKind::Yield(_) => {
+                for child in exp.children() {
+                    self.infer(child);
+                }
+                (Type::Any, false)
+            }
+        }
+    }
+
+    // The declared type of `obj.field`, if `obj` is known to have one.
+    fn declared_field(&mut self, obj: &Ident, field: &Ident, span: Span) -> Option<Type> {
+        if self.receiver.as_ref() == Some(&obj.name) {
+            return self.field(&self.cur_cls, &field.name)
+        }
+        match self.locals.get(&obj.name).cloned() {
+            Some(Type::Class(cls)) => self.field(&cls, &field.name),
+            Some(Type::Any) | None => None,
+            Some(other) => {
+                self.error(span, format!("{} has no field {}", other, field.name));
+                None
+            }
+        }
+    }
+
+    fn binary(&mut self, op: BinOp, lhs: Type, rhs: Type, span: Span) -> Type {
+        let (special, result) = match op {
+            BinOp::Add => ("__add__", Type::Int),
+            BinOp::Sub => ("__sub__", Type::Int),
+            BinOp::Lteq => ("__le__", Type::Bool),
+            BinOp::Gteq => ("__ge__", Type::Bool),
+            BinOp::Lt => ("__lt__", Type::Bool),
+            BinOp::Gt => ("__gt__", Type::Bool),
+            BinOp::Eqeq => ("__eq__", Type::Bool),
+        };
+        if let Type::Class(ref cls) = lhs {
+            if let Some(sig) = self.method(cls, special) {
+                return sig.ret
+            }
+        }
+        if op == BinOp::Eqeq {
+            return Type::Bool
+        }
+        match (&lhs, &rhs) {
+            (&Type::Int, &Type::Int) => result,
+            (&Type::Any, _) | (_, &Type::Any) => {
+                if result == Type::Bool { result } else { Type::Any }
+            }
+            _ => {
+                let msg = match op {
+                    BinOp::Add => format!("cannot add {} to {}", rhs, lhs),
+                    BinOp::Sub => format!("cannot subtract {} from {}", rhs, lhs),
+                    _ => format!("cannot compare {} with {}", lhs, rhs),
+                };
+                self.error(span, msg);
+                Type::Any
+            }
+        }
+    }
+
+    fn method_call(&mut self, receiver: &Type, name: &str, args: &[(Span, Type, bool)], span: Span) -> Type {
+        let cls = match *receiver {
+            Type::Any => return Type::Any,
+            Type::Class(ref cls) if self.enums.contains(cls) || self.is_imported(cls) => {
+                return Type::Any
+            }
+            Type::Class(ref cls) => cls,
+            _ => {
+                self.error(span, format!("{} has no method {}", receiver, name));
+                return Type::Any
+            }
+        };
+        match self.method(cls, name) {
+            Some(sig) => {
+                // Static methods don't receive the object.
+                let params = if sig.static_ || sig.params.is_empty() { &sig.params[..] }
+                             else { &sig.params[1..] };
+                self.check_args(&format!("{}.{}", cls, name), params, args);
+                sig.ret.clone()
+            }
+            // A field may hold a function value.
+            None if self.field(cls, name).is_some() => Type::Any,
+            // Methods inherited from imported classes aren't known here.
+            None if self.ancestors(cls).iter().any(|c| !self.classes.contains_key(c)) => Type::Any,
+            None => {
+                self.error(span, format!("{} has no method {}", cls, name));
+                Type::Any
+            }
+        }
+    }
+
+    fn check_args(&mut self, label: &str, params: &[(String, Type)], args: &[(Span, Type, bool)]) -> bool {
+        let mut proven = params.len() == args.len();
+        for (&(ref param, ref expected), &(arg, ref actual, arg_proven)) in params.iter().zip(args.iter()) {
+            if !self.assignable(actual, expected) {
+                let msg = format!("parameter {} of {} expects {}, got {}",
+                                  param, label, expected, actual);
+                self.error(arg, msg);
+            }
+            if *expected != Type::Any && !arg_proven {
+                proven = false;
+            }
+        }
+        proven
+    }
+
+    // Checks the arguments of a call to a function known at compile time.
+    fn check_call(&mut self, call: Span, label: &str, params: &[(String, Type)],
+                  args: &[(Span, Type, bool)]) {
+        if self.check_args(label, params, args) {
+            self.safe.insert(call);
+        }
+    }
+
+    // Infers the type of each argument, and whether it's proven.
+    fn args(&mut self, args: &[Expr]) -> Vec<(Span, Type, bool)> {
+        args.iter()
+            .map(|arg| {
+                let (arg_type, proven) = self.infer(arg);
+                (arg.span, arg_type, proven)
+            })
+            .collect()
+    }
+
+    fn assignable(&self, actual: &Type, expected: &Type) -> bool {
+        match (actual, expected) {
+            (&Type::Any, _) | (_, &Type::Any) => true,
+            (&Type::Class(ref cls), &Type::Class(ref other)) => {
+                let other = self.linked(other);
+                self.ancestors(cls).iter().any(|c| c == &other || self.implements(c, &other))
+            }
+            _ => actual == expected
+        }
+    }
+
+    // The class followed by its parents, nearest first. Imported classes
+    // are named as they are once linked.
+    fn ancestors(&self, cls: &str) -> Vec<String> {
+        let mut ancestors = Vec::new();
+        let mut next = Some(self.linked(cls));
+        while let Some(cls) = next {
+            if ancestors.contains(&cls) {
+                break
+            }
+            next = match (self.classes.get(&cls), self.imports.classes.get(&cls)) {
+                (Some(info), _) => info.parent.as_ref().map(|p| self.linked(p)),
+                (None, Some(class)) => class.parent.clone(),
+                (None, None) => None
+            };
+            ancestors.push(cls);
+        }
+        ancestors
+    }
+
+    // Whether the class, named as in `ancestors`, declares that it
+    // implements the trait.
+    fn implements(&self, cls: &str, trait_name: &str) -> bool {
+        match (self.classes.get(cls), self.imports.classes.get(cls)) {
+            (Some(info), _) => info.traits.iter().any(|t| self.linked(t) == trait_name),
+            (None, Some(class)) => class.traits.iter().any(|t| t == trait_name),
+            (None, None) => false
+        }
+    }
+
+    // The name a type is known by once linked, which for those declared in
+    // this module is the name they're declared with.
+    fn linked(&self, name: &str) -> String {
+        if self.classes.contains_key(name) || self.traits.contains_key(name) || self.enums.iter().any(|e| e == name) {
+            return name.to_string()
+        }
+        self.imports.names.get(name).cloned().unwrap_or_else(|| name.to_string())
+    }
+
+    fn is_imported(&self, name: &str) -> bool {
+        self.imports.classes.contains_key(name) || self.imports.traits.iter().any(|t| t == name)
+            || self.imports.enums.contains_key(name)
+    }
+
+    fn method(&self, cls: &str, name: &str) -> Option<Sig> {
+        if let Some(info) = self.traits.get(cls) {
+            return info.methods.get(name).cloned()
+        }
+        self.ancestors(cls).iter()
+            .filter_map(|c| self.classes.get(c).and_then(|info| info.methods.get(name)))
+            .next()
+            .cloned()
+    }
+
+    fn field(&self, cls: &str, name: &str) -> Option<Type> {
+        self.ancestors(cls).iter()
+            .filter_map(|c| self.classes.get(c).and_then(|info| info.fields.get(name)))
+            .next()
+            .cloned()
+    }
+
+    fn resolve(&mut self, type_name: &Ident) -> Type {
+        let name = &type_name.name;
+        match name.as_ref() {
+            "Any" => Type::Any,
+            "Int" => Type::Int,
+            "Str" => Type::Str,
+            "Bool" => Type::Bool,
+            "None" => Type::NoneType,
+            "Tuple" => Type::Tuple,
+            _ if self.classes.contains_key(name) || self.traits.contains_key(name)
+                 || self.enums.contains(name) || self.is_imported(name) => Type::Class(name.clone()),
+            _ => {
+                self.error(type_name.span, format!("unknown type {}", name));
+                Type::Any
+            }
+        }
+    }
+
+    // Both methods and trait method signatures have parameters and a return
+    // type.
+    fn signature(&mut self, params: &[Param], return_type: &Option<Ident>, static_: bool) -> Sig {
+        let params = params.iter()
+            .map(|param| {
+                let param_type = match param.type_name {
+                    Some(ref type_name) => self.resolve(type_name),
+                    None => Type::Any
+                };
+                (param.name.name.clone(), param_type)
+            })
+            .collect();
+        let ret = match *return_type {
+            Some(ref type_name) => self.resolve(type_name),
+            None => Type::Any
+        };
+        Sig { params: params, ret: ret, static_: static_ }
+    }
+
+    fn error(&mut self, span: Span, msg: String) {
+        self.errors.push(CompileError::new(self.input, span, msg));
+    }
+}
+
+// The locals which `block` assigns, outside of nested functions.
+fn assigned(block: &[Stmt]) -> Vec<String> {
+    let mut names = Vec::new();
+    for stmt in block.iter() {
+        match stmt.kind {
+            StmtKind::Let { ref target, .. } => names.extend(target.names().into_iter().map(|n| n.name.clone())),
+            StmtKind::Const(ref decl) => names.push(decl.name.name.clone()),
+            StmtKind::CompoundAssign { ref name, .. } => names.push(name.name.clone()),
+            StmtKind::If { ref body, .. } => names.extend(assigned(body)),
+            StmtKind::For { ref init, ref step, ref body, .. } => {
+                names.extend(assigned(::std::slice::from_ref(&**init)));
+                names.extend(assigned(::std::slice::from_ref(&**step)));
+                names.extend(assigned(body));
+            }
+            StmtKind::ForIn { ref target, ref body, .. } => {
+                names.extend(target.names().into_iter().map(|n| n.name.clone()));
+                names.extend(assigned(body));
+            }
+            StmtKind::Match { ref arms, .. } => {
+                for arm in arms.iter() {
+                    names.extend(arm.pattern.bindings().into_iter().map(|n| n.name.clone()));
+                    names.extend(assigned(&arm.body));
+                }
+            }
+            StmtKind::TryExcept { ref body, ref handler } => {
+                names.extend(assigned(body));
+                names.extend(assigned(handler));
+            }
+            StmtKind::Expr(_) | StmtKind::Assert { .. } | StmtKind::FuncDef(_) | StmtKind::Raise => ()
+        }
+    }
+    names
+}
+
+// Whether the block yields, making its function a generator. Functions
+// nested inside it are generators of their own.
+fn has_yield(block: &[Stmt]) -> bool {
+    block.iter().any(stmt_yields)
+}
+
+fn stmt_yields(stmt: &Stmt) -> bool {
+    match stmt.kind {
+        StmtKind::Expr(ref exp) | StmtKind::Let { value: ref exp, .. }
+            | StmtKind::CompoundAssign { value: ref exp, .. } => exp_yields(exp),
+        StmtKind::Const(ref decl) => exp_yields(&decl.value),
+        StmtKind::If { ref cond, ref body } => exp_yields(cond) || has_yield(body),
+        StmtKind::Assert { ref cond, ref msg } => exp_yields(cond) || msg.iter().any(exp_yields),
+        StmtKind::For { ref init, ref cond, ref step, ref body } => {
+            stmt_yields(init) || exp_yields(cond) || stmt_yields(step) || has_yield(body)
+        }
+        StmtKind::ForIn { ref iterable, ref body, .. } => exp_yields(iterable) || has_yield(body),
+        StmtKind::Match { ref scrutinee, ref arms } => {
+            exp_yields(scrutinee) || arms.iter().any(|arm| has_yield(&arm.body))
+        }
+        StmtKind::TryExcept { ref body, ref handler } => has_yield(body) || has_yield(handler),
+        StmtKind::FuncDef(_) | StmtKind::Raise => false
+    }
+}
+
+fn exp_yields(exp: &Expr) -> bool {
+    match exp.kind {
+        ExprKind::Yield(_) => true,
+        _ => exp.children().into_iter().any(exp_yields)
+    }
+}
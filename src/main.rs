@@ -2,19 +2,46 @@
 use std::vec::Vec;
 use std::path::Path;
 use std::env;
+use std::process;
 
 extern crate plang_rust;
-use plang_rust::parse::parse_file;
+use plang_rust::parse::{parse_source_file, CompileOptions};
 use plang_rust::interp::run;
+use plang_rust::project::parse_project;
 
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let ref source = &args[1];
-    let lex_path    = Path::new("grammar/lexer.l");
-    let yacc_path   = Path::new("grammar/grammar.y");
+    // `--release` compiles the program without its asserts and contracts,
+    // `--no-asserts` without just its assert statements.
+    let mut options = if args.iter().any(|a| a == "--release") {
+        CompileOptions::release()
+    }
+    else {
+        CompileOptions::default()
+    };
+    options.strip_asserts |= args.iter().any(|a| a == "--no-asserts");
+    // The program is either a single source file, or a project directory
+    // holding a plang.toml manifest.
+    let ref source = args.iter().skip(1).find(|a| !a.starts_with("--"))
+        .expect("No source file or project given");
     let source_path = Path::new(source);
-    let bytecode = parse_file(source_path, lex_path, yacc_path).unwrap();
+    let result = if source_path.is_dir() {
+        parse_project(source_path, &options)
+    }
+    else {
+        parse_source_file(source_path, &options)
+    };
+    let bytecode = match result {
+        Ok(bytecode) => bytecode,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    for warning in bytecode.warnings.iter() {
+        eprintln!("{}", warning);
+    }
     run(bytecode);
 }
 
@@ -1,18 +1,35 @@
 extern crate plang_rust;
+extern crate lrlex;
+extern crate lrpar;
 
-use std::path::Path;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use plang_rust::parse::parse_input;
-use plang_rust::parse::Bytecode;
-use plang_rust::interp::run;
+use plang_rust::parse::{parse_file, parse_input_with_options, parse_source, CompileOptions};
+use plang_rust::parse::{parse_tree, Bytecode, CompileError, DiagnosticKind, Grammar, ParseError, Parser};
+use plang_rust::ast::{self, BinOp, Decl, Expr, ExprKind, FuncDef, Literal, MemberKind, StmtKind, Target};
+use lrlex::Lexeme;
+use lrpar::parser::Node;
+use plang_rust::interp::{run, try_run};
+use plang_rust::project::parse_project;
 
 const LEX_PATH: &str = "grammar/lexer.l";
 const YACC_PATH: &str = "grammar/grammar.y";
 
+thread_local! {
+    // Building a parser is slow, so each test thread builds one, from tables
+    // cached between runs, and compiles all of its programs with it.
+    static PARSER: Parser = Parser::with_cache(&Path::new(LEX_PATH), &Path::new(YACC_PATH),
+                                               &env::temp_dir().join("plang_test_tables")).unwrap();
+}
+
+fn compile(source: String) -> Result<Bytecode, ParseError> {
+    PARSER.with(|parser| parser.parse_input(source, &CompileOptions::default()))
+}
+
 fn build_bytecode(source: String) -> Bytecode {
-    let lex_path = Path::new(LEX_PATH);
-    let yacc_path = Path::new(YACC_PATH);
-    parse_input(source, &lex_path, &yacc_path).unwrap()
+    compile(source).unwrap_or_else(|e| panic!("{}", e))
 }
 
 #[test]
@@ -383,3 +400,2552 @@ fn raise_exception() {
     assert_eq!(res, "");
 }
 
+
+#[test]
+fn tuple_literal() {
+    let src = "
+        class global() {
+            def main() {
+                (1, 2 + 3, (4,))
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(1, 5, (4,))");
+}
+
+#[test]
+fn tuple_index() {
+    let src = "
+        class global() {
+            def main() {
+                let t = (5, 6, 7);
+                t[2]
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "7");
+}
+
+#[test]
+fn let_destructure() {
+    let src = "
+        class global() {
+            def main() {
+                let (x, y) = pair();
+                x - y
+            };
+
+            def pair() {
+                (10, 4)
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "6");
+}
+
+#[test]
+fn let_destructure_shape_mismatch() {
+    let src = "
+        class global() {
+            def main() {
+                let (x, y) = (1, 2, 3);
+                x
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = try_run(bc);
+    assert_eq!(res, Err("ValueError: expected 2 values to unpack, got 3".to_string()));
+}
+
+#[test]
+fn for_in_range() {
+    let src = "
+        class global() {
+            def main() {
+                let total = 0;
+                for i in range(0, 5, 1) {
+                    let total = total + i
+                };
+                total
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "10");
+}
+
+#[test]
+fn method_hides_builtin() {
+    let src = "
+        class global() {
+            def main() {
+                range(3)
+            };
+
+            def range(n) {
+                n + 1
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "4");
+}
+
+#[test]
+fn for_in_tuple_destructure() {
+    let src = "
+        class global() {
+            def main() {
+                let total = 0;
+                for (a, b) in ((1, 2), (3, 4)) {
+                    let total = total + a - b
+                };
+                total
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "-2");
+}
+
+#[test]
+fn for_in_string() {
+    let src = "
+        class global() {
+            def main() {
+                let count = 0;
+                let last = 0;
+                for c in \"abc\" {
+                    let count = count + 1;
+                    let last = c
+                };
+                (count, last)
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(3, c)");
+}
+
+#[test]
+fn for_in_user_iterator() {
+    let src = "
+        class global() {
+            def main() {
+                let total = 0;
+                for x in new Counter(3) {
+                    let total = total + x
+                };
+                total
+            }
+        }
+
+        class Counter() {
+            def construct(self, n) {
+                self.n = n;
+                self.i = 0
+            };
+
+            def next(self) {
+                if self.i < self.n {
+                    self.i = self.i + 1;
+                    self.i
+                }
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "6");
+}
+
+#[test]
+fn for_in_iter_method() {
+    let src = "
+        class global() {
+            def main() {
+                let total = 0;
+                let three = new Three();
+                for x in three {
+                    for y in three {
+                        let total = total + 1
+                    }
+                };
+                total
+            }
+        }
+
+        class Three() {
+            def construct(self) {
+            };
+
+            def iter(self) {
+                new Counter(3)
+            }
+        }
+
+        class Counter() {
+            def construct(self, n) {
+                self.n = n;
+                self.i = 0
+            };
+
+            def next(self) {
+                if self.i < self.n {
+                    self.i = self.i + 1;
+                    self.i
+                }
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "9");
+}
+
+#[test]
+fn generator_for_in() {
+    let src = "
+        class global() {
+            def main() {
+                let total = 0;
+                for x in evens(7) {
+                    let total = total + x
+                };
+                total
+            };
+
+            def evens(n) {
+                for i in range(0, n, 2) {
+                    yield i
+                }
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "12");
+}
+
+#[test]
+fn for_in_none_elements() {
+    let src = "
+        class global() {
+            def main() {
+                let count = 0;
+                for x in (1, nothing(), 3) {
+                    let count = count + 1
+                };
+                for x in nones() {
+                    let count = count + 1
+                };
+                count
+            };
+
+            def nothing() {
+            };
+
+            def nones() {
+                yield nothing();
+                yield nothing()
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "5");
+}
+
+#[test]
+fn generator_send() {
+    let src = "
+        class global() {
+            def main() {
+                let acc = accumulate();
+                acc.next();
+                acc.send(5);
+                acc.send(10)
+            };
+
+            def accumulate() {
+                let total = 0;
+                for i in range(0, 100, 1) {
+                    let x = yield total;
+                    let total = total + x
+                }
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "15");
+}
+
+#[test]
+fn generator_exhausted() {
+    let src = "
+        class global() {
+            def main() {
+                let gen = two();
+                gen.next();
+                gen.next();
+                gen.next()
+            };
+
+            def two() {
+                yield 1;
+                yield 2
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "None");
+}
+
+#[test]
+fn match_enum() {
+    let src = "
+        enum Shape {
+            Circle(r),
+            Rect(w, h),
+            Empty
+        }
+
+        class global() {
+            def main() {
+                area(Shape.Rect(3, 4)) + area(Shape.Circle(2)) + area(Shape.Empty)
+            };
+
+            def area(s) {
+                match s {
+                    Shape.Circle(r) => { r + r + r },
+                    Shape.Rect(w, h) => { w + h },
+                    Shape.Empty => { 0 }
+                }
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    assert!(bc.warnings.is_empty());
+    let res = run(bc);
+    assert_eq!(res, "13");
+}
+
+#[test]
+fn match_tuple_literal_wildcard() {
+    let src = "
+        class global() {
+            def main() {
+                (classify((0, 0)), classify((0, 5)), classify((7, 1)))
+            };
+
+            def classify(p) {
+                match p {
+                    (0, 0) => { \"origin\" },
+                    (0, y) => { y },
+                    (x, _) => { x }
+                }
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(origin, 5, 7)");
+}
+
+#[test]
+fn eq_mismatched_types() {
+    let src = "
+        class global() {
+            def main() {
+                (kind(1), kind(\"one\"))
+            };
+
+            def kind(x) {
+                match x {
+                    \"one\" => { \"str\" },
+                    1 => { \"int\" }
+                }
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    assert_eq!(run(bc), "(int, str)");
+
+    let src = "
+        class global() {
+            def main() {
+                1 == \"one\"
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    assert_eq!(try_run(bc), Err("TypeError".to_string()));
+}
+
+#[test]
+fn match_non_exhaustive() {
+    let src = "
+        enum Shape {
+            Circle(r),
+            Empty
+        }
+
+        class global() {
+            def main() {
+                match Shape.Empty {
+                    Shape.Circle(r) => { r }
+                }
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    assert_eq!(bc.warnings.len(), 1);
+    assert!(bc.warnings[0].contains("missing Empty"));
+    let res = run(bc);
+    assert_eq!(res, "");
+}
+
+#[test]
+fn field_decl() {
+    let src = "
+        class global() {
+            def main() {
+                let p = new Point(5);
+                (p.x, p.y)
+            }
+        }
+
+        class Point() {
+            field x = 1;
+            field y = 2;
+
+            def construct(self, y) {
+                self.y = y
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(1, 5)");
+}
+
+#[test]
+fn static_field_and_method() {
+    let src = "
+        class global() {
+            def main() {
+                Counter.bump();
+                Counter.bump();
+                let c = new Counter();
+                c.bump();
+                Counter.count
+            }
+        }
+
+        class Counter() {
+            static field count = 10;
+
+            def construct(self) {
+            };
+
+            static def bump() {
+                Counter.count = Counter.count + 1
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "13");
+}
+
+#[test]
+fn inherited_field_decl() {
+    let src = "
+        class global() {
+            def main() {
+                let b = new Bar();
+                (b.x, b.y, b.get_x(), Bar.z)
+            }
+        }
+
+        class Foo() {
+            field x = 1;
+            static field z = 3;
+
+            def construct(self) {
+            };
+
+            def get_x(self) {
+                self.x
+            }
+        }
+
+        class Bar(Foo) {
+            field y = 2;
+
+            def construct(self) {
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(1, 2, 1, 3)");
+}
+
+#[test]
+fn default_constructor() {
+    let src = "
+        class global() {
+            def main() {
+                let p = new Point();
+                p.x
+            }
+        }
+
+        class Point() {
+            field x = 3
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "3");
+}
+
+#[test]
+fn constructor_this() {
+    let src = "
+        class global() {
+            def main() {
+                let a = new Foo();
+                let b = new Bar(5);
+                (a.x, b.x)
+            }
+        }
+
+        class Foo() {
+            def construct() {
+                this.x = 1
+            }
+        }
+
+        class Bar() {
+            def construct(self, x) {
+                this.x = x
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(1, 5)");
+}
+
+#[test]
+fn super_construct() {
+    let src = "
+        class global() {
+            def main() {
+                let b = new Bar(1, 2);
+                let c = new Baz(7);
+                (b.x, b.y, c.x)
+            }
+        }
+
+        class Foo() {
+            def construct(self, x) {
+                self.x = x
+            }
+        }
+
+        class Bar(Foo) {
+            def construct(self, x, y) {
+                super.construct(x);
+                self.y = y
+            }
+        }
+
+        class Baz(Foo) {
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(1, 2, 7)");
+}
+
+#[test]
+fn construct_call_rejected() {
+    let src = "
+        class global() {
+            def main() {
+                let a = new Foo();
+                a.construct()
+            }
+        }
+
+        class Foo() {
+        }
+    ";
+    let errors = compile_errors(src.to_string());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(format!("{}", errors[0]),
+               "Error at line 5, column 19: construct can only be called through new or super.construct");
+}
+
+#[test]
+fn private_members() {
+    let src = "
+        class global() {
+            def main() {
+                let a = new Account(5);
+                a.deposit(3);
+                a.balance()
+            }
+        }
+
+        class Account() {
+            private field total = 0;
+
+            def construct(self, x) {
+                self.add(x)
+            };
+
+            def deposit(self, x) {
+                self.add(x)
+            };
+
+            def balance(self) {
+                self.total
+            };
+
+            private def add(self, x) {
+                self.total = self.total + x
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "8");
+}
+
+#[test]
+fn private_field_runtime_access() {
+    let src = "
+        class global() {
+            def main() {
+                let a = new Account();
+                a.total
+            }
+        }
+
+        class Account() {
+            private field total = 0
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = try_run(bc);
+    assert_eq!(res, Err("AccessError: Account.total is private".to_string()));
+}
+
+#[test]
+fn runtime_field_errors() {
+    let src = "
+        class global() {
+            def main() {
+                let a = new Account();
+                get(a)
+            };
+
+            def get(obj) {
+                obj.total
+            }
+        }
+
+        class Account() {
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    assert_eq!(try_run(bc), Err("AttributeError: Account has no field total".to_string()));
+
+    let src = src.replace("get(a)", "get(4)");
+    let bc = build_bytecode(src.to_string());
+    assert_eq!(try_run(bc), Err("TypeError: Int has no fields".to_string()));
+
+    let src = src.replace("obj.total", "obj.total = 1");
+    let bc = build_bytecode(src.to_string());
+    assert_eq!(try_run(bc), Err("TypeError: Int has no fields".to_string()));
+}
+
+#[test]
+fn private_static_compile_access() {
+    let src = "
+        class global() {
+            def main() {
+                Counter.count
+            }
+        }
+
+        class Counter() {
+            private static field count = 0
+        }
+    ";
+    let errors = compile_errors(src.to_string());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(format!("{}", errors[0]),
+               "Error at line 4, column 25: AccessError: Counter.count is private");
+}
+
+#[test]
+fn trait_default_method() {
+    let src = "
+        class global() {
+            def main() {
+                let s = new Square(3);
+                (s.area(), s.describe(), implements(s, Shape), implements(5, Shape))
+            }
+        }
+
+        trait Shape {
+            def area(self);
+
+            def describe(self) {
+                self.area() + 100
+            }
+        }
+
+        class Square() implements Shape {
+            def construct(self, side) {
+                self.side = side
+            };
+
+            def area(self) {
+                self.side + self.side
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(6, 106, true, false)");
+}
+
+#[test]
+fn trait_inherited_impl() {
+    let src = "
+        class global() {
+            def main() {
+                let b = new Bar();
+                (b.name(), implements(b, Named), implements(new Baz(), Named))
+            }
+        }
+
+        trait Named {
+            def name(self);
+        }
+
+        class Bar(Foo) implements Named {
+        }
+
+        class Foo() {
+            def name(self) {
+                1
+            }
+        }
+
+        class Baz() {
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(1, true, false)");
+}
+
+#[test]
+fn trait_missing_method() {
+    let src = "
+        class global() {
+            def main() {
+            }
+        }
+
+        trait Shape {
+            def area(self);
+        }
+
+        class Foo() implements Shape {
+        }
+    ";
+    let errors = compile_errors(src.to_string());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(format!("{}", errors[0]),
+               "Error at line 11, column 15: Class Foo does not implement Shape.area");
+}
+
+#[test]
+fn operator_overloading() {
+    let src = "
+        class global() {
+            def main() {
+                let a = new Vec(1, 2);
+                let b = new Vec(3, 4);
+                let c = a + b;
+                (c.x, c.y, a == new Vec(1, 2), a == b, a < b)
+            }
+        }
+
+        class Vec() {
+            def construct(self, x, y) {
+                self.x = x;
+                self.y = y
+            };
+
+            def __add__(self, other) {
+                new Vec(self.x + other.x, self.y + other.y)
+            };
+
+            def __eq__(self, other) {
+                (self.x, self.y) == (other.x, other.y)
+            };
+
+            def __lt__(self, other) {
+                self.x < other.x
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(4, 6, true, false, true)");
+}
+
+#[test]
+fn object_str() {
+    let src = "
+        class global() {
+            def main() {
+                let a = new Foo();
+                (new Point(1), a, a == a, a == new Foo())
+            }
+        }
+
+        class Foo() {
+        }
+
+        class Point() {
+            def construct(self, x) {
+                self.x = x
+            };
+
+            def __str__(self) {
+                \"Point\"
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(Point, &0, true, false)");
+}
+
+#[test]
+fn special_str_raises() {
+    let src = "
+        class global() {
+            def main() {
+                (1, new Point())
+            }
+        }
+
+        class Point() {
+            def __str__(self) {
+                self.name
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = try_run(bc);
+    assert_eq!(res, Err("AttributeError: Point has no field name".to_string()));
+}
+
+#[test]
+fn reflection_type_isinstance() {
+    let src = "
+        class global() {
+            def main() {
+                let b = new Bar();
+                (type(b), type(b) == Bar, isinstance(b, Foo), isinstance(new Foo(), Bar), type(1))
+            }
+        }
+
+        class Foo() {
+        }
+
+        class Bar(Foo) {
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(<class Bar>, true, true, false, <class Int>)");
+}
+
+#[test]
+fn reflection_attrs() {
+    let src = "
+        class global() {
+            def main() {
+                let p = new Point();
+                setattr(p, \"y\", 2);
+                (getattr(p, \"x\"), p.y, hasattr(p, \"y\"), hasattr(p, \"z\"), fields(p), methods(Point))
+            }
+        }
+
+        class Base() {
+            def base(self) {
+            }
+        }
+
+        class Point(Base) {
+            field x = 1;
+
+            def norm(self) {
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(1, 2, true, false, (x, y), (base, construct, norm))");
+}
+
+#[test]
+fn decorator_wraps_method() {
+    let src = "
+        class global() {
+            def main() {
+                let c = new Calc();
+                (c.add1(5), Calc.double(4), Calc.double)
+            };
+
+            def twice(f) {
+                new Twice(f)
+            }
+        }
+
+        class Twice() {
+            def construct(self, f) {
+                self.f = f
+            };
+
+            def __call__(self, obj, x) {
+                self.f(obj, self.f(obj, x))
+            }
+        }
+
+        class Calc() {
+            @twice
+            def add1(self, x) {
+                x + 1
+            };
+
+            @Calc.traced
+            @Calc.traced
+            static def double(x) {
+                x + x
+            };
+
+            static def traced(f) {
+                f
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(7, 8, <function Calc.double>)");
+}
+
+#[test]
+fn function_values() {
+    let src = "
+        class global() {
+            def main() {
+                let f = inc;
+                (apply(f, 1), f == inc, f)
+            };
+
+            def inc(x) {
+                x + 1
+            };
+
+            def apply(f, x) {
+                f(x)
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(2, true, <function global.inc>)");
+}
+
+#[test]
+fn private_function_value_access() {
+    let src = "
+        class global() {
+            def main() {
+                let a = new Account();
+                let f = a.leak();
+                f(a)
+            }
+        }
+
+        class Account() {
+            def leak(self) {
+                Account.secret
+            };
+
+            private def secret(self) {
+                42
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = try_run(bc);
+    assert_eq!(res, Err("AccessError: Account.secret is private".to_string()));
+}
+
+#[test]
+fn const_bindings() {
+    let src = "
+        const LIMIT = 10;
+        const START = Config.base() + LIMIT;
+
+        class global() {
+            def main() {
+                const step = 2;
+                const twice = step + step;
+                let total = START;
+                total += twice;
+                (total, LIMIT, Config.NAME, Config.DOUBLE, step)
+            }
+        }
+
+        class Config() {
+            const NAME = \"cfg\";
+            const DOUBLE = LIMIT + LIMIT;
+
+            static def base() {
+                1
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(15, 10, cfg, 20, 2)");
+}
+
+#[test]
+fn const_literal_inlined() {
+    let src = "
+        const ANSWER = 42;
+
+        class global() {
+            def main() {
+                const x = 1;
+                ANSWER + x
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let instrs = format!("{:?}", bc.bytecode);
+    assert!(instrs.contains("PushInt(42)"));
+    assert!(!instrs.contains("LoadVar"));
+    assert!(!instrs.contains("LoadGlobal"));
+    let res = run(bc);
+    assert_eq!(res, "43");
+}
+
+#[test]
+fn const_reassign_rejected() {
+    let src = "
+        class global() {
+            def main() {
+                const x = 1;
+                let x = 2
+            }
+        }
+    ";
+    let errors = compile_errors(src.to_string());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(format!("{}", errors[0]), "Error at line 5, column 21: cannot assign to const x");
+}
+
+#[test]
+fn const_compound_assign_rejected() {
+    let src = "
+        const LIMIT = 10;
+
+        class global() {
+            def main() {
+                LIMIT += 1
+            }
+        }
+    ";
+    let errors = compile_errors(src.to_string());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(format!("{}", errors[0]), "Error at line 6, column 17: cannot assign to const LIMIT");
+}
+
+#[test]
+fn local_shadows_const() {
+    let src = "
+        const LIMIT = 10;
+
+        class global() {
+            def main() {
+                let LIMIT = 3;
+                LIMIT += 1;
+                (LIMIT, Config.size())
+            }
+        }
+
+        class Config() {
+            const SIZE = 5;
+
+            static def size() {
+                let SIZE = SIZE + 1;
+                SIZE
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "(4, 6)");
+}
+
+#[test]
+fn assert_passes() {
+    let src = "
+        class global() {
+            def main() {
+                let x = 3;
+                assert x > 2, \"x is too small\";
+                assert x == 3;
+                x
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "3");
+}
+
+#[test]
+fn assert_fails() {
+    let src = "
+        class global() {
+            def main() {
+                let x = 1;
+                assert x > 2, \"x is too small\";
+                x
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = try_run(bc);
+    assert_eq!(res, Err("AssertionError: x is too small at line 5, column 17".to_string()));
+}
+
+#[test]
+fn assert_stripped() {
+    let src = "
+        class global() {
+            def main() {
+                let x = 1;
+                assert x > 2, \"x is too small\";
+                x
+            }
+        }
+    ";
+    let mut options = CompileOptions::default();
+    options.strip_asserts = true;
+    let bc = parse_input_with_options(src.to_string(), &Path::new(LEX_PATH),
+                                      &Path::new(YACC_PATH), &options).unwrap();
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "1");
+}
+
+#[test]
+fn contract_requires_and_ensures() {
+    let src = "
+        class global() {
+            def main() {
+                let a = new Account(10);
+                a.withdraw(3);
+                a.withdraw(4)
+            }
+        }
+
+        class Account() {
+            field balance = 0;
+
+            def construct(self, balance) {
+                self.balance = balance
+            };
+
+            def withdraw(self, amount)
+                requires amount <= self.balance
+                ensures result == old(self.balance) - amount
+            {
+                self.balance = self.balance - amount;
+                self.balance
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "3");
+}
+
+#[test]
+fn contract_requires_fails() {
+    let src = "
+        class global() {
+            def main() {
+                half(3)
+            };
+
+            def half(n) requires n >= 0 requires n < 3 {
+                n
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = try_run(bc);
+    assert_eq!(res, Err("ContractError: half requires n < 3".to_string()));
+}
+
+#[test]
+fn contract_ensures_fails() {
+    let src = "
+        class global() {
+            def main() {
+                let c = new Counter();
+                c.bump()
+            }
+        }
+
+        class Counter() {
+            field n = 0;
+
+            def bump(self) ensures self.n == old(self.n) + 1 {
+                self.n = self.n + 2
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = try_run(bc);
+    assert_eq!(res, Err("ContractError: Counter.bump ensures self.n == old(self.n) + 1".to_string()));
+}
+
+#[test]
+fn contract_result_reserved() {
+    let src = "
+class global() {
+    def main() {
+        inc(1)
+    };
+
+    def inc(result) ensures result == 2 {
+        let (x, result) = (result, result + 1);
+        result
+    };
+
+    def dec(n) {
+        let result = n - 1;
+        result
+    }
+}";
+    let errors = compile_errors(src.to_string());
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages, vec![
+        "Error at line 7, column 13: cannot declare result in a function with ensures clauses",
+        "Error at line 8, column 17: cannot declare result in a function with ensures clauses",
+    ]);
+}
+
+#[test]
+fn contracts_stripped() {
+    let src = "
+        class global() {
+            def main() {
+                half(3)
+            };
+
+            def half(n) requires n < 3 ensures result < 0 {
+                n
+            }
+        }
+    ";
+    let bc = parse_input_with_options(src.to_string(), &Path::new(LEX_PATH),
+                                      &Path::new(YACC_PATH), &CompileOptions::release()).unwrap();
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "3");
+}
+
+fn type_errors(source: String) -> Vec<String> {
+    match compile(source) {
+        Err(ParseError::CompileError(errors)) => errors.iter().map(|e| e.to_string()).collect(),
+        _ => panic!("Expected a type error")
+    }
+}
+
+#[test]
+fn typed_functions() {
+    let src = "
+        class global() {
+            def main() {
+                let p: Point = new Point(2);
+                add(p.x, p.scale(3))
+            };
+
+            def add(x: Int, y: Int) -> Int {
+                x + y
+            }
+        }
+
+        class Point() {
+            field x: Int = 1;
+
+            def construct(self, x: Int) {
+                self.x = x
+            };
+
+            def scale(self, n: Int) -> Int {
+                self.x + n
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "7");
+}
+
+#[test]
+fn type_error_operands() {
+    let src = "
+        class global() {
+            def main() {
+                let x: Int = 1;
+                let y = x + \"one\";
+                let z: Str = 2;
+                y
+            }
+        }
+    ";
+    assert_eq!(type_errors(src.to_string()),
+               vec!["Error at line 5, column 25: cannot add Str to Int",
+                    "Error at line 6, column 21: z is declared as Str, got Int"]);
+}
+
+#[test]
+fn type_error_calls() {
+    let src = "
+        class global() {
+            def main() {
+                let p: Point = new Point();
+                p.move(1);
+                half(\"four\")
+            };
+
+            def half(n: Int) -> Str {
+                n - 2
+            }
+        }
+
+        class Point() {
+            def shift(self, dx: Int) {
+                dx
+            }
+        }
+    ";
+    assert_eq!(type_errors(src.to_string()),
+               vec!["Error at line 5, column 17: Point has no method move",
+                    "Error at line 6, column 22: parameter n of half expects Int, got Str",
+                    "Error at line 9, column 17: half is declared to return Str, got Int"]);
+}
+
+#[test]
+fn type_error_self_field() {
+    let src = "
+        class global() {
+            def main() {
+                let c = new Counter();
+                c.reset()
+            }
+        }
+
+        class Counter() {
+            field x: Int = 0;
+
+            def reset(self) {
+                self.x = \"s\"
+            }
+        }
+    ";
+    assert_eq!(type_errors(src.to_string()),
+               vec!["Error at line 13, column 17: field self.x is declared as Int, got Str"]);
+}
+
+#[test]
+fn untyped_code_unchecked() {
+    let src = "
+        class global() {
+            def main() {
+                let x = 1;
+                let x = \"one\";
+                x
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "one");
+}
+
+#[test]
+fn untyped_method_through_class() {
+    let src = "
+        class global() {
+            def main() {
+                let c = new Counter();
+                Counter.set(c, Math.inc(5));
+                Counter.get(c)
+            }
+        }
+
+        class Math() {
+            def inc(n) {
+                n + 1
+            }
+        }
+
+        class Counter() {
+            field count: Int = 0;
+
+            def set(self, n) {
+                self.count = n
+            };
+
+            def get(self) {
+                self.count
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "6");
+}
+
+#[test]
+fn runtime_param_check() {
+    let src = "
+        class global() {
+            def main() {
+                let s = \"four\";
+                half(s)
+            };
+
+            def half(n: Int) {
+                n
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    assert!(bc.bytecode.iter().any(|i| format!("{:?}", i) == "Call(\"global\", \"half\")"));
+    assert_eq!(try_run(bc), Err("TypeError: parameter n of half expects Int, got Str".to_string()));
+}
+
+#[test]
+fn runtime_return_check() {
+    let src = "
+        class global() {
+            def main() {
+                ident(\"four\")
+            };
+
+            def ident(x) -> Int {
+                x
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    assert_eq!(try_run(bc), Err("TypeError: ident should return Int, got Str".to_string()));
+}
+
+#[test]
+fn runtime_check_annotated_local() {
+    let src = "
+        class global() {
+            def main() {
+                let n: Int = ident(\"four\");
+                half(n)
+            };
+
+            def ident(x) {
+                x
+            };
+
+            def half(n: Int) -> Int {
+                n
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    assert!(bc.bytecode.iter().any(|i| format!("{:?}", i) == "Call(\"global\", \"half\")"));
+    assert_eq!(try_run(bc), Err("TypeError: parameter n of half expects Int, got Str".to_string()));
+
+    let src = "
+        class global() {
+            def main() {
+                four()
+            };
+
+            def ident(x) {
+                x
+            };
+
+            def four() -> Int {
+                let n: Int = ident(\"four\");
+                n
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    assert_eq!(try_run(bc), Err("TypeError: four should return Int, got Str".to_string()));
+}
+
+#[test]
+fn runtime_checks_subclass_and_trait() {
+    let src = "
+        class global() {
+            def main() {
+                let c = new Circle();
+                let s = new Square();
+                area(c) + area(s)
+            };
+
+            def area(s: Shape) -> Int {
+                s.area()
+            }
+        }
+
+        trait Shape {
+            def area(self);
+        }
+
+        class Circle() implements Shape {
+            def area(self) {
+                3
+            }
+        }
+
+        class Square(Circle) {
+            def area(self) {
+                4
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "7");
+}
+
+#[test]
+fn proven_checks_elided() {
+    let src = "
+        class global() {
+            def main() {
+                add(1, 2)
+            };
+
+            def add(x: Int, y: Int) -> Int {
+                x + y
+            }
+        }
+    ";
+    let bc = build_bytecode(src.to_string());
+    println!("{:?}", bc);
+    assert!(bc.bytecode.iter().any(|i| format!("{:?}", i) == "CallUnchecked(\"global\", \"add\")"));
+    let res = run(bc);
+    assert_eq!(res, "3");
+}
+
+// Writes each (name, source) module to `name.plang` in a fresh directory.
+fn write_modules(dir_name: &str, modules: &[(&str, &str)]) -> PathBuf {
+    let dir = env::temp_dir().join(dir_name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for &(name, source) in modules {
+        fs::write(dir.join(format!("{}.plang", name)), source).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn import_module() {
+    let dir = write_modules("plang_import_module", &[
+        ("main", "
+            import geometry;
+            from geometry import Point, double;
+
+            class global() {
+                def main() {
+                    let p = new Point(3);
+                    (p.x, double(p.x), geometry.triple(p.x))
+                }
+            }
+        "),
+        ("geometry", "
+            class Point() {
+                def construct(self, x) {
+                    self.x = x
+                }
+            }
+
+            class global() {
+                def double(x) {
+                    x + x
+                };
+
+                def triple(x) {
+                    x + double(x)
+                }
+            }
+        "),
+    ]);
+    let bc = parse_file(&dir.join("main.plang"), &Path::new(LEX_PATH), &Path::new(YACC_PATH)).unwrap();
+    println!("{:?}", bc);
+    assert!(bc.symbols.contains_key(&("geometry.Point".to_string(), "construct".to_string())));
+    assert!(bc.symbols.contains_key(&("geometry.global".to_string(), "triple".to_string())));
+    let res = run(bc);
+    assert_eq!(res, "(3, 6, 9)");
+}
+
+#[test]
+fn import_arity_error() {
+    let dir = write_modules("plang_import_arity", &[
+        ("main", "
+import geometry;
+from geometry import Point, double;
+
+class global() {
+    def main() {
+        let p = new Point();
+        double(p, 2);
+        geometry.triple()
+    }
+}"),
+        ("geometry", "
+class Point() {
+    def construct(self, x) {
+        self.x = x
+    }
+}
+
+class global() {
+    def double(x) {
+        x + x
+    };
+
+    def triple(x) {
+        x + double(x)
+    }
+}"),
+    ]);
+    let errors = match parse_file(&dir.join("main.plang"), &Path::new(LEX_PATH), &Path::new(YACC_PATH)) {
+        Err(ParseError::CompileError(errors)) => errors,
+        _ => panic!("Expected compile errors")
+    };
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages, vec![
+        "Error at line 7, column 17: Point.construct takes 1 argument, got 0",
+        "Error at line 8, column 9: double takes 1 argument, got 2",
+        "Error at line 9, column 9: geometry.triple takes 1 argument, got 0",
+    ]);
+}
+
+#[test]
+fn import_compiled_once() {
+    let dir = write_modules("plang_import_once", &[
+        ("main", "
+            from shapes import Square;
+            from base import Shape;
+
+            class global() {
+                def main() {
+                    let s: Shape = new Square();
+                    (s.sides(), s.name())
+                }
+            }
+        "),
+        ("shapes", "
+            from base import Shape;
+
+            class Square(Shape) {
+                def sides(self) {
+                    4
+                }
+            }
+        "),
+        ("base", "
+            class Shape() {
+                def name(self) {
+                    \"shape\"
+                }
+            }
+        "),
+    ]);
+    let bc = parse_file(&dir.join("main.plang"), &Path::new(LEX_PATH), &Path::new(YACC_PATH)).unwrap();
+    println!("{:?}", bc);
+    assert_eq!(bc.classes["shapes.Square"].parent, Some("base.Shape".to_string()));
+    // base is imported twice, but linked once.
+    assert_eq!(bc.classes["base.Shape"].methods, vec!["name".to_string()]);
+    let mut labels: Vec<&str> = bc.labels.keys()
+        .filter(|&&(ref c, _)| c == "base.Shape")
+        .map(|&(_, ref f)| f.as_ref())
+        .collect();
+    labels.sort();
+    assert_eq!(labels, vec!["%init", "construct", "name"]);
+    let bodies = bc.bytecode.iter().filter(|i| format!("{:?}", i) == "PushStr(\"shape\")").count();
+    assert_eq!(bodies, 1);
+    let res = run(bc);
+    assert_eq!(res, "(4, shape)");
+}
+
+#[test]
+fn import_type_hierarchy() {
+    let modules = [
+        ("shapes", "
+            from base import Shape;
+
+            class Square(Shape) {
+            }
+
+            class Point() {
+            }
+        "),
+        ("base", "
+            class Shape() {
+            }
+        "),
+    ];
+    let main = "
+        from shapes import Square, Point;
+        from base import Shape;
+
+        class global() {
+            def main() {
+                wrap(new Square())
+            };
+
+            def wrap(sq: Square) -> Int {
+                area(sq)
+            };
+
+            def area(s: Shape) -> Int {
+                4
+            }
+        }
+    ";
+    let dir = write_modules("plang_import_types", &modules);
+    fs::write(dir.join("main.plang"), main).unwrap();
+    let bc = parse_file(&dir.join("main.plang"), &Path::new(LEX_PATH), &Path::new(YACC_PATH)).unwrap();
+    println!("{:?}", bc);
+    assert_eq!(run(bc), "4");
+
+    let main = main.replace("sq: Square", "sq: Point");
+    fs::write(dir.join("main.plang"), main).unwrap();
+    let errors = match parse_file(&dir.join("main.plang"), &Path::new(LEX_PATH), &Path::new(YACC_PATH)) {
+        Err(ParseError::CompileError(errors)) => errors,
+        _ => panic!("Expected a type error")
+    };
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages, vec!["Error at line 11, column 22: parameter s of area expects Shape, got Point"]);
+}
+
+#[test]
+fn import_search_path() {
+    let lib = write_modules("plang_import_lib", &[
+        ("util", "
+            class global() {
+                def answer() {
+                    42
+                }
+            }
+        "),
+    ]);
+    let src = "
+        from util import answer;
+
+        class global() {
+            def main() {
+                answer()
+            }
+        }
+    ";
+    let mut options = CompileOptions::default();
+    options.search_path.push(lib);
+    let bc = parse_input_with_options(src.to_string(), &Path::new(LEX_PATH),
+                                      &Path::new(YACC_PATH), &options).unwrap();
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "42");
+}
+
+#[test]
+fn import_name_clash() {
+    let first = write_modules("plang_import_clash_first", &[
+        ("a", "
+            import util;
+
+            class global() {
+                def f() {
+                    util.answer()
+                }
+            }
+        "),
+        ("util", "
+            class global() {
+                def answer() {
+                    1
+                }
+            }
+        "),
+    ]);
+    let second = write_modules("plang_import_clash_second", &[
+        ("b", "
+            import util;
+
+            class global() {
+                def g() {
+                    util.answer()
+                }
+            }
+        "),
+        ("util", "
+            class global() {
+                def answer() {
+                    2
+                }
+            }
+        "),
+    ]);
+    let src = "
+        import a;
+        import b;
+
+        class global() {
+            def main() {
+                (a.f(), b.g())
+            }
+        }
+    ";
+    let mut options = CompileOptions::default();
+    options.search_path.push(first.clone());
+    options.search_path.push(second.clone());
+    let msg = format!("{} and {} are both imported as util",
+                      fs::canonicalize(first.join("util.plang")).unwrap().display(),
+                      fs::canonicalize(second.join("util.plang")).unwrap().display());
+    match parse_input_with_options(src.to_string(), &Path::new(LEX_PATH), &Path::new(YACC_PATH), &options) {
+        Err(ParseError::ImportError(err)) => assert_eq!(err, msg),
+        _ => panic!("Expected an import error")
+    }
+}
+
+#[test]
+fn import_cycle() {
+    let dir = write_modules("plang_import_cycle", &[
+        ("main", "
+            import a;
+
+            class global() {
+                def main() {
+                    a.f()
+                }
+            }
+        "),
+        ("a", "
+            import b;
+
+            class global() {
+                def f() {
+                    1
+                }
+            }
+        "),
+        ("b", "
+            import a;
+
+            class global() {
+                def g() {
+                    2
+                }
+            }
+        "),
+    ]);
+    match parse_file(&dir.join("main.plang"), &Path::new(LEX_PATH), &Path::new(YACC_PATH)) {
+        Err(ParseError::ImportError(msg)) => assert_eq!(msg, "import cycle: main -> a -> b -> a"),
+        _ => panic!("Expected an import cycle")
+    }
+}
+
+#[test]
+fn project_with_dependency() {
+    let geometry = write_modules("plang_project_geometry", &[
+        ("geometry", "
+            class Point() {
+                def construct(self, x) {
+                    self.x = x
+                }
+            }
+        "),
+    ]);
+    fs::write(geometry.join("plang.toml"), "
+        [project]
+        name = \"geometry\"
+        main = \"geometry.plang\"
+    ").unwrap();
+    let app = write_modules("plang_project_app", &[
+        ("app", "
+            from geometry import Point;
+            from util import double;
+
+            class App() {
+                static def start() {
+                    let p = new Point(21);
+                    double(p.x)
+                }
+            }
+        "),
+    ]);
+    fs::create_dir_all(app.join("lib")).unwrap();
+    fs::write(app.join("lib").join("util.plang"), "
+        class global() {
+            def double(x) {
+                x + x
+            }
+        }
+    ").unwrap();
+    fs::write(app.join("plang.toml"), "
+        [project]
+        name = \"app\"
+        main = \"app.plang\"
+        entry = \"App.start\"
+        search_path = [\"lib\"]
+
+        [dependencies]
+        geometry = { path = \"../plang_project_geometry\" }
+    ").unwrap();
+    let bc = parse_project(&app, &CompileOptions::default()).unwrap();
+    println!("{:?}", bc);
+    let res = run(bc);
+    assert_eq!(res, "42");
+}
+
+#[test]
+fn project_errors() {
+    let dir = write_modules("plang_project_entry", &[
+        ("main", "
+            class App() {
+                def start(self) {
+                    1
+                }
+            }
+        "),
+    ]);
+    fs::write(dir.join("plang.toml"), "
+        [project]
+        name = \"entry\"
+        entry = \"App.start\"
+    ").unwrap();
+    match parse_project(&dir, &CompileOptions::default()) {
+        Err(ParseError::ProjectError(msg)) =>
+            assert_eq!(msg, "entry point App.start must be static and take no arguments"),
+        _ => panic!("Expected a bad entry point")
+    }
+
+    fs::write(dir.join("plang.toml"), "
+        [project]
+        name = \"entry\"
+
+        [dependencies]
+        entry = { path = \".\" }
+    ").unwrap();
+    match parse_project(&dir, &CompileOptions::default()) {
+        Err(ParseError::ProjectError(msg)) => assert!(msg.ends_with("dependency cycle through entry")),
+        _ => panic!("Expected a dependency cycle")
+    }
+}
+
+#[test]
+fn builtin_grammar() {
+    let src = "
+        class global() {
+            def add3(a, b, c) {
+                a + b + c
+            };
+
+            def main() {
+                let x = add3(1, 2, 3);
+                if x > 5 {
+                    let x = x - 1
+                };
+                x
+            }
+        }
+    ";
+    let bc = parse_source(src).unwrap();
+    println!("{:?}", bc);
+    let from_files = build_bytecode(src.to_string());
+    assert_eq!(format!("{:?}", bc.bytecode), format!("{:?}", from_files.bytecode));
+    let res = run(bc);
+    assert_eq!(res, "5");
+}
+
+#[test]
+fn parser_reuse() {
+    let parser = Parser::new(&Path::new(LEX_PATH), &Path::new(YACC_PATH)).unwrap();
+    let options = CompileOptions::default();
+    for i in 0..3 {
+        let src = format!("
+            class global() {{
+                def main() {{
+                    {} + 1
+                }}
+            }}
+        ", i);
+        let bc = parser.parse_input(src, &options).unwrap();
+        println!("{:?}", bc);
+        let res = run(bc);
+        assert_eq!(res, (i + 1).to_string());
+    }
+    match parser.parse_input("class global( {".to_string(), &options) {
+        Err(ParseError::Diagnostics(..)) => (),
+        _ => panic!("Expected a syntax error")
+    }
+}
+
+#[test]
+fn parser_table_cache() {
+    let cache_dir = env::temp_dir().join("plang_table_cache");
+    let _ = fs::remove_dir_all(&cache_dir);
+    let src = "
+        class global() {
+            def main() {
+                let x = 40;
+                x + 2
+            }
+        }
+    ";
+    let options = CompileOptions::default();
+    let parser = Parser::with_cache(&Path::new(LEX_PATH), &Path::new(YACC_PATH), &cache_dir).unwrap();
+    let bc = parser.parse_input(src.to_string(), &options).unwrap();
+    assert_eq!(run(bc), "42");
+    let cached: Vec<PathBuf> = fs::read_dir(&cache_dir).unwrap().map(|e| e.unwrap().path()).collect();
+    assert_eq!(cached.len(), 1);
+    assert_eq!(cached[0].extension().unwrap(), "tables");
+
+    // The second parser is built from the cached tables.
+    let parser = Parser::with_cache(&Path::new(LEX_PATH), &Path::new(YACC_PATH), &cache_dir).unwrap();
+    let bc = parser.parse_input(src.to_string(), &options).unwrap();
+    println!("{:?}", bc);
+    assert_eq!(run(bc), "42");
+
+    // A damaged cache is regenerated.
+    fs::write(&cached[0], b"not tables").unwrap();
+    let parser = Parser::with_cache(&Path::new(LEX_PATH), &Path::new(YACC_PATH), &cache_dir).unwrap();
+    let bc = parser.parse_input(src.to_string(), &options).unwrap();
+    assert_eq!(run(bc), "42");
+    assert!(fs::read(&cached[0]).unwrap() != b"not tables");
+}
+
+#[test]
+fn syntax_error_location() {
+    let src = "
+class global() {
+    def main() {
+        let x = );
+        x
+    }
+}";
+    match compile(src.to_string()) {
+        Err(ParseError::Diagnostics(diagnostics, _)) => {
+            assert_eq!(diagnostics.len(), 1);
+            let d = &diagnostics[0];
+            assert_eq!((d.line, d.column), (4, 17));
+            assert_eq!(d.lexeme, ")");
+            assert!(d.expected.contains(&"INT_LITERAL".to_string()));
+            assert!(!d.expected.contains(&"RPAREN".to_string()));
+            let rendered = format!("{}", d);
+            let lines: Vec<&str> = rendered.lines().collect();
+            assert!(lines[0].starts_with("Error at line 4, column 17: unexpected ')', expected one of "));
+            assert_eq!(lines[1], "        let x = );");
+            assert_eq!(lines[2], "                ^");
+        }
+        _ => panic!("Expected a syntax error")
+    }
+    // The grammar built into the binary reports the same error.
+    match parse_source(src) {
+        Err(ParseError::Diagnostics(diagnostics, _)) =>
+            assert_eq!((diagnostics[0].line, diagnostics[0].column), (4, 17)),
+        _ => panic!("Expected a syntax error")
+    }
+}
+
+#[test]
+fn syntax_error_end_of_input() {
+    let src = "class global() {\n    def main() {\n        1\n";
+    match compile(src.to_string()) {
+        Err(ParseError::Diagnostics(diagnostics, _)) => {
+            let d = &diagnostics[0];
+            assert_eq!((d.line, d.column), (3, 10));
+            assert_eq!(d.lexeme, "");
+            assert!(d.message.starts_with("unexpected end of input, expected"));
+        }
+        _ => panic!("Expected a syntax error")
+    }
+}
+
+#[test]
+fn lexical_error_location() {
+    let src = "
+class global() {
+    def main() {
+        1 $ 2
+    }
+}";
+    match compile(src.to_string()) {
+        Err(ParseError::Diagnostics(diagnostics, _)) => {
+            assert_eq!(format!("{}", diagnostics[0]),
+                       "Error at line 4, column 11: unexpected character '$'\n        1 $ 2\n          ^");
+            // Lexing carries on past the unmatched character.
+            let kinds: Vec<DiagnosticKind> = diagnostics.iter().map(|d| d.kind).collect();
+            assert_eq!(kinds, vec![DiagnosticKind::Lexical, DiagnosticKind::Syntax]);
+            assert_eq!(diagnostics[1].lexeme, "2");
+        }
+        _ => panic!("Expected a lexical error")
+    }
+}
+
+#[test]
+fn syntax_errors_recovered() {
+    let src = "
+class global() {
+    def f(x) {
+        let y = x +;
+        y
+    };
+
+    def g() {
+        let z = (1, 2;
+        z
+    };
+
+    def main() {
+        f(1) g()
+    }
+}";
+    match compile(src.to_string()) {
+        Err(e @ ParseError::Diagnostics(..)) => {
+            println!("{}", e);
+            match e {
+                ParseError::Diagnostics(diagnostics, prog) => {
+                    let lines: Vec<usize> = diagnostics.iter().map(|d| d.line).collect();
+                    assert_eq!(lines, vec![4, 9, 14]);
+                    // The AST of the repaired source comes with them.
+                    let methods: Vec<String> = match prog.unwrap().decls[0] {
+                        Decl::Class(ref class) => class.members.iter().map(|m| m.name().name.clone()).collect(),
+                        _ => panic!("Expected a class")
+                    };
+                    assert_eq!(methods, vec!["f", "g", "main"]);
+                }
+                _ => unreachable!()
+            }
+        }
+        _ => panic!("Expected syntax errors")
+    }
+    // The tree of the repaired source is still available.
+    let parser = Parser::new(&Path::new(LEX_PATH), &Path::new(YACC_PATH)).unwrap();
+    let (tree, diagnostics) = parser.parse_tree(src);
+    assert!(tree.is_some());
+    assert_eq!(diagnostics.len(), 3);
+}
+
+fn compile_errors(source: String) -> Vec<CompileError> {
+    match compile(source) {
+        Err(ParseError::CompileError(errors)) => errors,
+        Err(e) => panic!("Expected compile errors, got {}", e),
+        Ok(_) => panic!("Expected compile errors")
+    }
+}
+
+#[test]
+fn undefined_variable_error() {
+    let src = "
+class global() {
+    def main() {
+        let x = 1;
+        x + y
+    }
+}";
+    let errors = compile_errors(src.to_string());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(format!("{}", errors[0]), "Error at line 5, column 13: undefined variable y");
+    assert_eq!(&src[errors[0].span.0..errors[0].span.1], "y");
+}
+
+#[test]
+fn int_literal_overflow_error() {
+    let src = "
+const BIG = 3000000000;
+
+class global() {
+    def main() {
+        BIG
+    }
+}";
+    let errors = compile_errors(src.to_string());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(format!("{}", errors[0]),
+               "Error at line 2, column 13: integer literal 3000000000 is too large");
+}
+
+#[test]
+fn compile_errors_collected() {
+    let src = "
+class global() {
+    def main() {
+        count += 1;
+        Color.Purple;
+        construct()
+    }
+}
+
+enum Color { Red, Green }";
+    let errors = compile_errors(src.to_string());
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages, vec![
+        "Error at line 4, column 9: undefined variable count",
+        "Error at line 5, column 15: Enum Color has no variant Purple",
+        "Error at line 6, column 9: construct can only be called through new or super.construct",
+    ]);
+}
+
+#[test]
+fn unknown_names_error() {
+    let src = "
+class global() {
+    def main() {
+        let p = new Pont(1, 2);
+        Point.move(p);
+        greet();
+        half(\"one\");
+        q.move()
+    };
+
+    def half(n: Int) -> Int {
+        n - 2
+    }
+}
+
+class Point() {
+    def construct(self, x, y) {
+        self.x = x
+    }
+}";
+    let errors = compile_errors(src.to_string());
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages, vec![
+        "Error at line 4, column 21: Unknown class Pont",
+        "Error at line 5, column 15: Class Point has no method move",
+        "Error at line 6, column 9: undefined function greet",
+        "Error at line 7, column 14: parameter n of half expects Int, got Str",
+        "Error at line 8, column 9: undefined variable q",
+    ]);
+}
+
+#[test]
+fn arity_mismatch_error() {
+    let src = "
+class global() {
+    def main() {
+        let p = new Point(1);
+        Point.origin(p);
+        add(1, 2, 3);
+        new Empty(4);
+        add(new Point(1, 2), new Empty())
+    };
+
+    def add(x, y) {
+        x + y
+    }
+}
+
+class Point() {
+    def construct(self, x, y) {
+        self.x = x
+    };
+
+    static def origin() {
+        new Point(0, 0)
+    }
+}
+
+class Empty() {
+}
+
+class Point3(Point) {
+    def construct(self, x, y, z) {
+        super.construct(x);
+        self.z = z
+    }
+}";
+    let errors = compile_errors(src.to_string());
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages, vec![
+        "Error at line 4, column 17: Point.construct takes 2 arguments, got 1",
+        "Error at line 5, column 9: Point.origin takes 0 arguments, got 1",
+        "Error at line 6, column 9: add takes 2 arguments, got 3",
+        "Error at line 7, column 9: Empty.construct takes 0 arguments, got 1",
+        "Error at line 31, column 9: super.construct takes 2 arguments, got 1",
+    ]);
+}
+
+#[test]
+fn arity_and_type_errors_together() {
+    let src = "
+class global() {
+    def main() {
+        half(\"one\", 2);
+        let p = new Point(\"x\");
+        Point.scale(p, \"two\", 3)
+    };
+
+    def half(n: Int) -> Int {
+        n - 2
+    }
+}
+
+class Point() {
+    def construct(self, x: Int, y: Int) {
+        self.x = x
+    };
+
+    static def scale(p, k: Int) {
+        k
+    }
+}";
+    let errors = compile_errors(src.to_string());
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages, vec![
+        "Error at line 4, column 9: half takes 1 argument, got 2",
+        "Error at line 4, column 14: parameter n of half expects Int, got Str",
+        "Error at line 5, column 17: Point.construct takes 2 arguments, got 1",
+        "Error at line 5, column 27: parameter x of Point.construct expects Int, got Str",
+        "Error at line 6, column 9: Point.scale takes 2 arguments, got 3",
+        "Error at line 6, column 24: parameter k of Point.scale expects Int, got Str",
+    ]);
+    // Compiling again reports the same errors in the same order.
+    let again: Vec<String> = compile_errors(src.to_string()).iter().map(|e| e.to_string()).collect();
+    assert_eq!(again, messages);
+}
+
+#[test]
+fn duplicate_definitions_error() {
+    let src = "
+class global() {
+    def main() {
+        0
+    };
+
+    def main() {
+        1
+    };
+
+    def add(x, x) {
+        x
+    }
+}";
+    let errors = compile_errors(src.to_string());
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages, vec![
+        "Error at line 7, column 9: main is already defined",
+        "Error at line 11, column 16: add has more than one parameter named x",
+    ]);
+}
+
+// Empties the lexeme spanning `text`, as though error recovery had inserted
+// it.
+fn empty_lexeme(node: &mut Node<u16>, src: &str, text: &str) {
+    match *node {
+        Node::Term { ref mut lexeme } => {
+            if &src[lexeme.start()..lexeme.start() + lexeme.len()] == text {
+                *lexeme = Lexeme::new(lexeme.tok_id(), lexeme.start(), 0);
+            }
+        }
+        Node::Nonterm { ref mut nodes, .. } => {
+            for node in nodes.iter_mut() {
+                empty_lexeme(node, src, text);
+            }
+        }
+    }
+}
+
+#[test]
+fn ast_inserted_string_literal() {
+    let src = "
+class global() {
+    def main() {
+        f(\"s\")
+    }
+}";
+    let (names, mut pt) = parse_tree(src, Grammar::Builtin).unwrap();
+    empty_lexeme(&mut pt, src, "\"s\"");
+    let prog = ast::build(&pt, names, src);
+    let body = match prog.decls[0] {
+        Decl::Class(ref class) => match class.members[0].kind {
+            MemberKind::Method(ref f) => &f.body,
+            _ => panic!("Expected a method")
+        },
+        _ => panic!("Expected a class")
+    };
+    match body[0].kind {
+        StmtKind::Expr(Expr { kind: ExprKind::Call { ref args, .. }, .. }) => match args[0].kind {
+            ExprKind::Literal(Literal::Str(ref s)) => assert_eq!(s, ""),
+            _ => panic!("Expected a string")
+        },
+        _ => panic!("Expected a call")
+    }
+}
+
+#[test]
+fn ast_from_parse_tree() {
+    let src = "
+class global() {
+    def main() {
+        let (a, b) = (1, 2);
+        add(a, b)
+    };
+
+    static def add(x, y: Int) -> Int {
+        x + y
+    }
+}";
+    let (names, pt) = parse_tree(src, Grammar::Builtin).unwrap();
+    let prog = ast::build(&pt, names, src);
+    assert_eq!(prog.decls.len(), 1);
+    let class = match prog.decls[0] {
+        Decl::Class(ref class) => class,
+        _ => panic!("Expected a class")
+    };
+    assert_eq!(class.name.name, "global");
+    let funcs: Vec<&FuncDef> = class.members.iter()
+        .map(|m| match m.kind {
+            MemberKind::Method(ref f) => f,
+            _ => panic!("Expected a method")
+        })
+        .collect();
+    assert!(!class.members[0].is_static && class.members[1].is_static);
+
+    let main = funcs[0];
+    assert_eq!(main.body.len(), 2);
+    match main.body[0].kind {
+        StmtKind::Let { target: Target::Tuple(ref names), ref value, .. } => {
+            let names: Vec<&str> = names.iter().map(|n| n.name.as_ref()).collect();
+            assert_eq!(names, vec!["a", "b"]);
+            assert_eq!(&src[value.span.0..value.span.1], "(1, 2)");
+        }
+        _ => panic!("Expected a let")
+    }
+    match main.body[1].kind {
+        StmtKind::Expr(Expr { kind: ExprKind::Call { ref name, ref args }, .. }) => {
+            assert_eq!(name.name, "add");
+            assert_eq!(args.len(), 2);
+        }
+        _ => panic!("Expected a call")
+    }
+
+    let add = funcs[1];
+    let types: Vec<Option<&str>> = add.params.iter()
+        .map(|p| p.type_name.as_ref().map(|t| t.name.as_ref()))
+        .collect();
+    assert_eq!(types, vec![None, Some("Int")]);
+    assert_eq!(add.return_type.as_ref().unwrap().name, "Int");
+    match add.body[0].kind {
+        StmtKind::Expr(Expr { kind: ExprKind::Binary { op: BinOp::Add, .. }, span }) => {
+            assert_eq!(&src[span.0..span.1], "x + y");
+        }
+        _ => panic!("Expected an addition")
+    }
+}